@@ -0,0 +1,79 @@
+#[macro_use]
+extern crate criterion;
+extern crate error_reporter;
+
+use std::rc::Rc;
+
+use criterion::{black_box, Criterion};
+use error_reporter::*;
+
+fn span(lo: usize, hi: usize) -> Span {
+    Span {
+        lo: BytePos(lo as u32),
+        hi: BytePos(hi as u32),
+        expn_id: NO_EXPANSION,
+    }
+}
+
+// A single 200 character source line with 5 labeled annotations on it
+fn long_line_message() -> CompilerMessage {
+    let mut line = String::new();
+    while line.len() < 200 {
+        line.push_str("vec.push(vec.pop().unwrap()); ");
+    }
+    line.truncate(200);
+    let file_text = format!("fn foo() {{\n{}\n}}\n", line);
+
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", &file_text);
+    let line_start = foo.start_pos.0 as usize + "fn foo() {\n".len();
+
+    let primary_span = span(line_start, line_start + 3);
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       primary_span,
+                                       Some(String::from("E123")),
+                                       cm);
+    for i in 0..5 {
+        let lo = line_start + i * 40;
+        err.span_label(span(lo, lo + 8), Some(format!("label number {}", i)));
+    }
+    err
+}
+
+// One labeled annotation in each of 10 files
+fn multi_file_message() -> CompilerMessage {
+    let file_text = "fn foo() {\n    vec.push(vec.pop().unwrap());\n}\n";
+    let cm = Rc::new(CodeMap::new());
+    let files: Vec<_> = (0..10)
+        .map(|i| cm.new_filemap_and_lines(&format!("file{}.rs", i), file_text))
+        .collect();
+
+    let spans: Vec<_> = files.iter()
+        .map(|f| {
+            let lo = f.start_pos.0 as usize + "fn foo() {\n    ".len();
+            span(lo, lo + 3)
+        })
+        .collect();
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       spans[0],
+                                       Some(String::from("E123")),
+                                       cm);
+    for (i, &sp) in spans.iter().enumerate() {
+        err.span_label(sp, Some(format!("label number {}", i)));
+    }
+    err
+}
+
+fn bench_render(c: &mut Criterion) {
+    let long_line = long_line_message();
+    c.bench_function("render_long_line", |b| b.iter(|| render_succinct(black_box(&long_line))));
+
+    let multi_file = multi_file_message();
+    c.bench_function("render_multi_file", |b| b.iter(|| render_succinct(black_box(&multi_file))));
+}
+
+criterion_group!(benches, bench_render);
+criterion_main!(benches);
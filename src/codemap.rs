@@ -28,9 +28,6 @@ use std::cmp;
 use std::{fmt, fs};
 use std::io::{self, Read};
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
-pub struct Name(pub u32);
-
 // _____________________________________________________________________________
 // Pos, BytePos, CharPos
 //
@@ -152,6 +149,26 @@ pub struct SpanLabel {
     pub label: Option<String>,
 }
 
+impl SpanLabel {
+    /// A primary label, eg) `SpanLabel::primary(span, String::from("expected `()`"))`
+    pub fn primary<L: Into<Option<String>>>(span: Span, label: L) -> SpanLabel {
+        SpanLabel {
+            span: span,
+            is_primary: true,
+            label: label.into(),
+        }
+    }
+
+    /// A secondary label, eg) `SpanLabel::secondary(span, None)`
+    pub fn secondary<L: Into<Option<String>>>(span: Span, label: L) -> SpanLabel {
+        SpanLabel {
+            span: span,
+            is_primary: false,
+            label: label.into(),
+        }
+    }
+}
+
 pub const DUMMY_SP: Span = Span {
     lo: BytePos(0),
     hi: BytePos(0),
@@ -340,11 +357,7 @@ impl MultiSpan {
 
         for &span in &self.primary_spans {
             if !span_labels.iter().any(|sl| sl.span == span) {
-                span_labels.push(SpanLabel {
-                    span: span,
-                    is_primary: true,
-                    label: None,
-                });
+                span_labels.push(SpanLabel::primary(span, None));
             }
         }
 
@@ -363,7 +376,7 @@ impl From<Span> for MultiSpan {
 //
 
 /// A source code location used for error reporting
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Loc {
     /// Information about the original source
     pub file: Rc<FileMap>,
@@ -405,9 +418,9 @@ pub struct FileMapAndBytePos {
 #[derive(Clone, Hash, Debug, PartialEq, Eq)]
 pub enum ExpnFormat {
     /// e.g. #[derive(...)] <item>
-    MacroAttribute(Name),
+    MacroAttribute(String),
     /// e.g. `format!()`
-    MacroBang(Name),
+    MacroBang(String),
 }
 
 #[derive(Clone, Hash, Debug)]
@@ -425,10 +438,10 @@ pub struct NameAndSpan {
 }
 
 impl NameAndSpan {
-    pub fn name(&self) -> Name {
+    pub fn name(&self) -> &str {
         match self.format {
-            ExpnFormat::MacroAttribute(s) => s,
-            ExpnFormat::MacroBang(s) => s,
+            ExpnFormat::MacroAttribute(ref s) => s,
+            ExpnFormat::MacroBang(ref s) => s,
         }
     }
 }
@@ -691,6 +704,13 @@ impl CodeMap {
             // register the start of this line
             fm.next_line(BytePos(byte_pos));
 
+            // register any multi-byte characters so that columns count characters, not bytes
+            for (offset, c) in line.char_indices() {
+                if c.len_utf8() > 1 {
+                    fm.record_multibyte_char(BytePos(byte_pos + offset as u32), c.len_utf8());
+                }
+            }
+
             // update byte_pos to include this line and the \n at the end
             byte_pos += line.len() as u32 + 1;
         }
@@ -1228,7 +1248,7 @@ impl CodeMap {
                         MacroAttribute(..) => ("#[", "]"),
                         MacroBang(..) => ("", "!"),
                     };
-                    let macro_decl_name = format!("{}{:?}{}",
+                    let macro_decl_name = format!("{}{}{}",
                                                   pre,
                                                   ei.callee.name(),
                                                   post);
@@ -1558,7 +1578,7 @@ mod tests {
             hi: BytePos(11),
             expn_id: NO_EXPANSION,
         };
-        let format = ExpnFormat::MacroBang(Name(0u32));
+        let format = ExpnFormat::MacroBang(String::from("foo"));
         let callee = NameAndSpan {
             format: format,
             allow_internal_unstable: false,
@@ -1591,7 +1611,7 @@ mod tests {
             expn_id: NO_EXPANSION,
         };
 
-        let format_root = ExpnFormat::MacroBang(Name(0u32));
+        let format_root = ExpnFormat::MacroBang(String::from("root"));
         let callee_root = NameAndSpan {
             format: format_root,
             allow_internal_unstable: false,
@@ -1609,7 +1629,7 @@ mod tests {
             expn_id: id_a1,
         };
 
-        let format_a = ExpnFormat::MacroBang(Name(1u32));
+        let format_a = ExpnFormat::MacroBang(String::from("a"));
         let callee_a = NameAndSpan {
             format: format_a,
             allow_internal_unstable: false,
@@ -1638,7 +1658,7 @@ mod tests {
             expn_id: id_b1,
         };
 
-        let format_b = ExpnFormat::MacroBang(Name(2u32));
+        let format_b = ExpnFormat::MacroBang(String::from("b"));
         let callee_b = NameAndSpan {
             format: format_b,
             allow_internal_unstable: false,
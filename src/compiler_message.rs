@@ -1,10 +1,62 @@
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
 use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::Path;
 use std::rc::Rc;
 
 use term;
+#[cfg(feature = "regex")]
+use regex::Regex;
 
 use styled_buffer::*;
-use codemap::{self, Span, CharPos, FileMap, SpanLabel};
+use renderer::{Renderer, RopeWriter, write_rope, write_plain, write_ansi};
+use render_succinct::{render_succinct, render_to_string_lossy, char_width_map, source_line_at,
+                      debug_repr, render_source_lines_sorted_by_span};
+use codemap::{self, Span, BytePos, CharPos, FileMap, SpanLabel, Pos, DUMMY_SP, NO_EXPANSION};
+
+pub struct Suggestion {
+    pub span: Span,
+    pub replacement: String,
+
+    /// Can this suggestion be applied automatically (eg, as an IDE quick-fix)
+    pub machine_applicable: bool,
+}
+
+/// Transforms the text of labels before they're rendered, eg) to add severity prefixes,
+/// truncate long labels or translate them. Closures taking the label and whether it's
+/// primary can be used as formatters.
+pub trait LabelFormatter {
+    fn format(&self, label: &str, is_primary: bool) -> String;
+}
+
+impl<F: Fn(&str, bool) -> String> LabelFormatter for F {
+    fn format(&self, label: &str, is_primary: bool) -> String {
+        self(label, is_primary)
+    }
+}
+
+/// The result of a pass that can fail with a message, so that `?` can pass the message on
+pub type DiagnosticResult<T> = Result<T, CompilerMessage>;
+
+/// The outcome of `CompilerMessage::apply_machine_applicable_fix`
+#[derive(Debug, PartialEq)]
+pub enum FixResult {
+    /// The source of the fix's file, with the fix and every fix applied to that file
+    /// before it
+    Applied(String),
+
+    /// The fix couldn't be applied, eg) because the source of its span isn't available or
+    /// it overlaps a fix already applied. It stays first in line.
+    Failed,
+
+    /// There are no fixes left to apply
+    Exhausted,
+}
 
 pub struct CompilerMessage {
     pub level: Level,
@@ -12,7 +64,57 @@ pub struct CompilerMessage {
     pub primary_msg: String,
     pub span_labels: Vec<SpanLabel>,
     pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
     pub error_code: Option<String>,
+
+    /// Extra text for the end of the header, eg) the name of the lint that fired
+    pub title_suffix: Option<String>,
+
+    /// An explicit (file, line, col) to report instead of the location of the primary span
+    pub location_override: Option<(String, usize, usize)>,
+
+    /// Source lines for files whose text isn't available in the codemap, by file name
+    pub in_memory_files: HashMap<String, Vec<String>>,
+    pub backtrace: Option<Backtrace>,
+
+    /// Skip the annotated source when rendering, for messages without a meaningful location
+    pub suppress_source: bool,
+
+    /// Skip the header when rendering, for tools that show the level and message elsewhere
+    pub suppress_header: bool,
+
+    /// Set by `suppress_if_duplicate` when another message was already reported at the
+    /// same location. The message still renders, but isn't emitted along with others.
+    pub suppressed: bool,
+
+    /// Place underlines where right-to-left text is displayed, rather than where it is stored
+    pub rtl_support: bool,
+
+    /// Only show these columns of each source line, for lines too wide to show in full
+    pub visible_column_range: Option<Range<usize>>,
+
+    /// The number of blank rows to put before and after the rendered message
+    pub padding: usize,
+
+    /// Applied to the replacement text of each suggestion before it is rendered
+    pub suggestion_formatter: Option<Box<Fn(&str) -> String>>,
+
+    /// Applied to the text of each label before it is rendered, along with whether the
+    /// label is primary
+    pub label_formatter: Option<Box<LabelFormatter>>,
+
+    /// Other messages linked to this one, eg) the earlier definition in a redefinition error
+    pub related: Vec<CompilerMessage>,
+
+    /// Fixes that work out their replacement from the source they replace, only once
+    /// they're applied with `apply_machine_applicable_fix`
+    pub machine_applicable_fixes: Vec<(Span, Box<FnOnce(&str) -> String>)>,
+
+    /// The fixes applied so far, so that each one builds on the ones before it
+    applied_fixes: Vec<(Span, String)>,
+
+    /// Calls the `on_cancel` callback if the message is dropped without being rendered
+    cancel_guard: CancelGuard,
     pub cm: Rc<codemap::CodeMap>,
 }
 
@@ -31,6 +133,418 @@ impl CompilerMessage {
         self
     }
 
+    pub fn with_machine_applicable_suggestion(&mut self,
+                                              span: Span,
+                                              replacement: String)
+                                              -> &mut CompilerMessage {
+        self.suggestions.push(Suggestion {
+            span: span,
+            replacement: replacement,
+            machine_applicable: true,
+        });
+        self
+    }
+
+    /// Like `with_machine_applicable_suggestion`, but the replacement is only worked out once
+    /// the fix is applied, by calling `f` with the source text of `span`. This saves the
+    /// work for fixes that are never used, eg) quick-fixes that an editor only offers.
+    pub fn with_machine_applicable_fix(&mut self,
+                                       span: Span,
+                                       f: Box<FnOnce(&str) -> String>)
+                                       -> &mut CompilerMessage {
+        self.machine_applicable_fixes.push((span, f));
+        self
+    }
+
+    /// Append `suffix` to the header after the message, eg) `[clippy::restriction]`
+    pub fn with_title_suffix(&mut self, suffix: &str) -> &mut CompilerMessage {
+        self.title_suffix = Some(String::from(suffix));
+        self
+    }
+
+    /// Use `cm` to look up the message's spans from now on, eg) a codemap that the spans'
+    /// files were added to after the message was created
+    pub fn set_codemap(&mut self, cm: Rc<codemap::CodeMap>) -> &mut CompilerMessage {
+        self.cm = cm;
+        self
+    }
+
+    /// Report the message at the given location rather than at the primary span, for
+    /// sources (eg, config file parsers) that don't have a codemap. As in the `-->` line,
+    /// `line` counts from 1 and `col` from 0.
+    pub fn set_file_line_col(&mut self,
+                             file: String,
+                             line: usize,
+                             col: usize)
+                             -> &mut CompilerMessage {
+        self.location_override = Some((file, line, col));
+        self
+    }
+
+    /// Capture the Rust call stack alongside the message, eg) for internal compiler errors.
+    /// The backtrace is only rendered when `RUST_BACKTRACE` is set.
+    pub fn with_backtrace(&mut self, bt: Backtrace) -> &mut CompilerMessage {
+        self.backtrace = Some(bt);
+        self
+    }
+
+    /// Render only the header (and any notes), even if labels are attached, eg) for linker
+    /// errors that have no useful source location
+    pub fn suppress_source_display(&mut self) -> &mut CompilerMessage {
+        self.suppress_source = true;
+        self
+    }
+
+    /// Render without the header line, starting straight with the source, eg) for a
+    /// sidebar that already shows the level and message
+    pub fn without_header(&mut self) -> &mut CompilerMessage {
+        self.suppress_header = true;
+        self
+    }
+
+    /// Drop the labels of spans starting at or after column `col` (0-based, counting
+    /// characters), eg) for very wide lines. The spans are still underlined, and a note
+    /// says how many labels were hidden.
+    pub fn collapse_labels_beyond_column(&mut self, col: usize) -> &mut CompilerMessage {
+        let mut hidden = 0;
+        for span_label in &mut self.span_labels {
+            let start_col = self.cm.lookup_char_pos(span_label.span.lo).col.0;
+            if span_label.label.is_some() && start_col >= col {
+                span_label.label = None;
+                hidden += 1;
+            }
+        }
+
+        if hidden > 0 {
+            self.notes.push(format!("... and {} labels beyond column {} not shown", hidden, col));
+        }
+        self
+    }
+
+    /// Lay out lines containing right-to-left text (eg, Arabic or Hebrew identifiers) the
+    /// way a terminal displays them, so that underlines appear under the right characters
+    pub fn set_rtl_support(&mut self, enable: bool) -> &mut CompilerMessage {
+        self.rtl_support = enable;
+        self
+    }
+
+    /// Only show the columns in `range` of each source line, with `...` marking where the
+    /// line has been cut off. Annotations are moved to match.
+    pub fn with_visible_column_range(&mut self, range: Range<usize>) -> &mut CompilerMessage {
+        self.visible_column_range = Some(range);
+        self
+    }
+
+    /// Surround the rendered message with `lines` blank rows, to separate it from whatever
+    /// is shown around it
+    pub fn with_padding(&mut self, lines: usize) -> &mut CompilerMessage {
+        self.padding = lines;
+        self
+    }
+
+    /// Call `f` if the message is dropped without being rendered, eg) to count the
+    /// messages that were suppressed rather than emitted
+    pub fn on_cancel(&mut self, f: Box<FnOnce()>) -> &mut CompilerMessage {
+        self.cancel_guard.on_cancel = Some(f);
+        self
+    }
+
+    /// Record that the message has been shown, so that the `on_cancel` callback isn't
+    /// called when it's dropped. Every renderer does this, but a client that only reads the
+    /// labels and draws the message itself can call it too.
+    pub fn mark_emitted(&self) {
+        self.cancel_guard.emitted.set(true);
+    }
+
+    /// Pass the replacement text of every suggestion through `f` before rendering, eg) to
+    /// reindent it with rustfmt
+    pub fn with_suggestion_formatter(&mut self,
+                                     f: Box<Fn(&str) -> String>)
+                                     -> &mut CompilerMessage {
+        self.suggestion_formatter = Some(f);
+        self
+    }
+
+    /// The replacement text of the suggestion as it should be rendered
+    pub fn formatted_replacement(&self, suggestion: &Suggestion) -> String {
+        match self.suggestion_formatter {
+            Some(ref f) => f(&suggestion.replacement),
+            None => suggestion.replacement.clone(),
+        }
+    }
+
+    /// Pass the text of every label through `f` before rendering, eg) to prefix secondary
+    /// labels with the name of a lint. `f` is also told whether the label is primary.
+    pub fn with_label_format<F>(&mut self, f: F) -> &mut CompilerMessage
+        where F: Fn(&str, bool) -> String + 'static
+    {
+        self.label_formatter = Some(Box::new(f));
+        self
+    }
+
+    /// Pass the text of every label through `formatter` before rendering
+    pub fn with_label_formatter(&mut self, formatter: Box<LabelFormatter>) -> &mut CompilerMessage {
+        self.label_formatter = Some(formatter);
+        self
+    }
+
+    /// The text of a label as it should be rendered
+    pub fn formatted_label(&self, label: &str, is_primary: bool) -> String {
+        match self.label_formatter {
+            Some(ref formatter) => formatter.format(label, is_primary),
+            None => label.to_string(),
+        }
+    }
+
+    /// Attach related messages, which are rendered indented after this one
+    pub fn with_related(&mut self, related: Vec<CompilerMessage>) -> &mut CompilerMessage {
+        self.related.extend(related);
+        self
+    }
+
+    /// Add an in-memory source file to the codemap, so that spans can be made into it
+    /// without touching the filesystem. Use `snippet_file` to get at the file afterwards.
+    pub fn with_snippet_context(&mut self, file: &str, content: String) -> &mut CompilerMessage {
+        self.cm.new_filemap_and_lines(file, &content);
+        self
+    }
+
+    /// The file called `file` in the codemap, eg) one added by `with_snippet_context`
+    pub fn snippet_file(&self, file: &str) -> Option<Rc<FileMap>> {
+        self.cm.files.borrow().iter().find(|fm| fm.name == file).cloned()
+    }
+
+    /// Provide the source lines for a file that is in the codemap without its text (eg,
+    /// an imported filemap), so that they can still be shown
+    pub fn with_context_file(&mut self, file: &str, lines: Vec<String>) -> &mut CompilerMessage {
+        self.in_memory_files.insert(file.to_string(), lines);
+        self
+    }
+
+    /// Add a secondary label at every match of `pattern` in the source of the file that
+    /// `span_of_file` is in, eg) to point out each use of a name in a quick lint
+    #[cfg(feature = "regex")]
+    pub fn annotate_from_regex(&mut self,
+                               span_of_file: Span,
+                               pattern: &Regex,
+                               label: Option<String>)
+                               -> &mut CompilerMessage {
+        let file = self.cm.lookup_char_pos(span_of_file.lo).file;
+        let src = match file.src {
+            Some(ref src) => src.clone(),
+            None => return self,
+        };
+
+        for m in pattern.find_iter(&src) {
+            let span = Span {
+                lo: file.start_pos + BytePos(m.start() as u32),
+                hi: file.start_pos + BytePos(m.end() as u32),
+                expn_id: NO_EXPANSION,
+            };
+            self.span_labels.push(SpanLabel::secondary(span, label.clone()));
+        }
+        self
+    }
+
+    /// Sort the span labels by where they start, with primary labels first among labels
+    /// that start at the same position
+    pub fn sort_labels_by_span(&mut self) {
+        self.span_labels.sort_by_key(|x| (x.span.lo, !x.is_primary));
+    }
+
+    /// Only keep the span labels for which `f` returns true, eg) to drop the labels in
+    /// files outside of the project
+    pub fn filter_labels<F: Fn(&SpanLabel) -> bool>(&mut self, f: F) -> &mut CompilerMessage {
+        self.span_labels.retain(|label| f(label));
+        self
+    }
+
+    /// Release any room left over in the span labels, eg) after many were removed from a
+    /// message that is kept around for a long time
+    pub fn shrink_to_fit(&mut self) {
+        self.span_labels.shrink_to_fit();
+    }
+
+    /// Returns the number of (primary, secondary) span labels
+    pub fn label_count(&self) -> (usize, usize) {
+        let primary = self.span_labels.iter().filter(|x| x.is_primary).count();
+        (primary, self.span_labels.len() - primary)
+    }
+
+    /// Returns the number of span labels
+    pub fn len(&self) -> usize {
+        self.span_labels.len()
+    }
+
+    /// Returns true if the message has no span labels
+    pub fn is_empty(&self) -> bool {
+        self.span_labels.is_empty()
+    }
+
+    /// Work out the replacement for the oldest fix given to `with_machine_applicable_fix`
+    /// that hasn't been applied yet, and return the source of its file with the fix
+    /// applied, on top of the fixes already applied to that file. From then on the fix is
+    /// shown as a suggestion like any other. A fix that fails is kept, and nothing is
+    /// recorded for it.
+    pub fn apply_machine_applicable_fix(&mut self) -> FixResult {
+        let span = match self.machine_applicable_fixes.first() {
+            Some(&(span, _)) => span,
+            None => return FixResult::Exhausted,
+        };
+        let snippet = match self.cm.span_to_snippet(span) {
+            Ok(snippet) => snippet,
+            Err(_) => return FixResult::Failed,
+        };
+
+        // Whether the fix applies doesn't depend on its replacement, so check before
+        // calling the closure, which can only be called once
+        if self.apply_fixes_with(span, "").is_none() {
+            return FixResult::Failed;
+        }
+
+        let (_, f) = self.machine_applicable_fixes.remove(0);
+        let replacement = f(&snippet);
+        let output = self.apply_fixes_with(span, &replacement).unwrap();
+        self.applied_fixes.push((span, replacement.clone()));
+        self.with_machine_applicable_suggestion(span, replacement);
+        FixResult::Applied(output)
+    }
+
+    /// The source of the file containing `span`, with the fixes applied to that file so far
+    /// and `replacement` in place of `span`
+    fn apply_fixes_with(&self, span: Span, replacement: &str) -> Option<String> {
+        let file_start = self.cm.lookup_byte_offset(span.lo).fm.start_pos;
+        let mut edits: Vec<(Span, &str)> = self.applied_fixes
+            .iter()
+            .filter(|&&(s, _)| self.cm.lookup_byte_offset(s.lo).fm.start_pos == file_start)
+            .map(|&(s, ref r)| (s, &r[..]))
+            .collect();
+        edits.push((span, replacement));
+        self.apply_edits(edits)
+    }
+
+    /// Returns the full contents of the file containing `span`, with the text covered by
+    /// the span replaced by `replacement`. Returns None if the source of the file isn't
+    /// available, or if the span is backwards or runs from one file into another.
+    pub fn apply_suggestion(&self, span: Span, replacement: &str) -> Option<String> {
+        self.apply_edits(vec![(span, replacement)])
+    }
+
+    /// The source of the file the edits are in, with each span replaced by its text.
+    /// Returns None unless the source is available and the spans are all in that one file
+    /// without overlapping.
+    fn apply_edits(&self, mut edits: Vec<(Span, &str)>) -> Option<String> {
+        edits.sort_by_key(|&(span, _)| span.lo);
+
+        let mut file: Option<Rc<FileMap>> = None;
+        let mut output = String::new();
+        let mut last_pos = 0;
+        for (span, replacement) in edits {
+            if span.hi < span.lo {
+                return None;
+            }
+
+            let lo = self.cm.lookup_byte_offset(span.lo);
+            let hi = self.cm.lookup_byte_offset(span.hi);
+            if lo.fm.start_pos != hi.fm.start_pos {
+                return None;
+            }
+            if let Some(ref file) = file {
+                if file.start_pos != lo.fm.start_pos {
+                    return None;
+                }
+            }
+
+            let src = match lo.fm.src {
+                Some(ref src) => src.clone(),
+                None => return None,
+            };
+            if lo.pos.to_usize() < last_pos {
+                return None;
+            }
+            output.push_str(&src[last_pos..lo.pos.to_usize()]);
+            output.push_str(replacement);
+            last_pos = hi.pos.to_usize();
+            file = Some(lo.fm);
+        }
+
+        let src = match file {
+            Some(ref file) => file.src.clone().unwrap(),
+            None => return None,
+        };
+        output.push_str(&src[last_pos..]);
+        Some(output)
+    }
+
+    /// Mark this message as suppressed if one has already been seen at the same primary
+    /// location, recording the location in `seen` otherwise. Returns true if it was
+    /// suppressed.
+    pub fn suppress_if_duplicate(&mut self, seen: &mut HashSet<(String, usize, usize)>) -> bool {
+        let loc = self.cm.lookup_char_pos(self.primary_span.lo);
+        if seen.insert((loc.file.name.clone(), loc.line, loc.col.0)) {
+            false
+        } else {
+            self.suppressed = true;
+            true
+        }
+    }
+
+    /// Returns the file name and (1-based) line number that `span` starts on, or None if
+    /// the span doesn't point into the codemap
+    pub fn file_and_line_for_span(&self, span: Span) -> Option<(String, usize)> {
+        let in_codemap = match self.cm.files.borrow().last() {
+            Some(last) => span.lo <= last.end_pos,
+            None => false,
+        };
+        if !in_codemap {
+            return None;
+        }
+
+        // Files without line information give us a line of 0
+        let loc = self.cm.lookup_char_pos(span.lo);
+        if loc.line == 0 {
+            None
+        } else {
+            Some((loc.file.name.clone(), loc.line))
+        }
+    }
+
+    /// The (1-based) line number the primary span starts on, or None if it doesn't point
+    /// into the codemap
+    pub fn primary_line_number(&self) -> Option<usize> {
+        self.file_and_line_for_span(self.primary_span).map(|(_, line)| line)
+    }
+
+    /// Every span the message refers to: the primary span, then the span of each label.
+    /// The primary span will usually appear twice, as most messages also label it.
+    pub fn spans<'a>(&'a self) -> impl Iterator<Item = Span> + 'a {
+        Some(self.primary_span).into_iter().chain(self.span_labels.iter().map(|x| x.span))
+    }
+
+    /// The first label whose span covers the given position in the primary span's file, eg)
+    /// for showing a hover at the cursor. `line` is 1-based and `col` is a 0-based
+    /// character column.
+    pub fn annotation_at_position(&self, line: usize, col: usize) -> Option<&SpanLabel> {
+        let primary_file = match self.file_and_line_for_span(self.primary_span) {
+            Some((file, _)) => file,
+            None => return None,
+        };
+
+        self.span_labels.iter().find(|span_label| {
+            let lo = self.cm.lookup_char_pos(span_label.span.lo);
+            let hi = self.cm.lookup_char_pos(span_label.span.hi);
+            if lo.file.name != primary_file {
+                return false;
+            }
+
+            let pos = (line, col);
+            let start = (lo.line, lo.col.0);
+            let end = (hi.line, hi.col.0);
+            // Empty spans still cover the position they're at
+            (start <= pos && pos < end) || (start == pos && start == end)
+        })
+    }
+
     pub fn new(level: Level,
                msg: String,
                primary_span: Span,
@@ -43,9 +557,235 @@ impl CompilerMessage {
             primary_span: primary_span,
             primary_msg: msg,
             error_code: error_code,
+            title_suffix: None,
             span_labels: vec![],
             notes: vec![],
+            suggestions: vec![],
+            location_override: None,
+            in_memory_files: HashMap::new(),
+            backtrace: None,
+            suppress_source: false,
+            suppress_header: false,
+            suppressed: false,
+            rtl_support: false,
+            visible_column_range: None,
+            padding: 0,
+            suggestion_formatter: None,
+            label_formatter: None,
+            related: vec![],
+            machine_applicable_fixes: vec![],
+            applied_fixes: vec![],
+            cancel_guard: CancelGuard {
+                on_cancel: None,
+                emitted: Cell::new(false),
+            },
             cm: cm,
         }
     }
-}
\ No newline at end of file
+
+    /// Create an error for a file that couldn't be read. The message has no source to
+    /// show, so it will only render the header.
+    pub fn from_io_error(e: io::Error, path: &Path) -> CompilerMessage {
+        CompilerMessage::spanless(Level::Error,
+                                  format!("cannot read file '{}': {}", path.display(), e))
+    }
+
+    /// Create a message that isn't about any particular source location, eg) a bad command
+    /// line option. Only the header (and any notes) will be rendered.
+    pub fn spanless(level: Level, msg: String) -> CompilerMessage {
+        let mut message = CompilerMessage::new(level,
+                                               msg,
+                                               DUMMY_SP,
+                                               None,
+                                               Rc::new(codemap::CodeMap::new()));
+        message.suppress_source = true;
+        message
+    }
+
+    /// Create an error with a codemap of its own holding the files at `paths`, ready for
+    /// spans into any of them. The primary span and message still need to be set.
+    pub fn with_code_map_from_paths(paths: &[&Path]) -> io::Result<CompilerMessage> {
+        let cm = Rc::new(codemap::CodeMap::new());
+        for path in paths {
+            let mut src = String::new();
+            try!(try!(File::open(path)).read_to_string(&mut src));
+            cm.new_filemap_and_lines(&path.to_string_lossy(), &src);
+        }
+
+        Ok(CompilerMessage::new(Level::Error, String::new(), DUMMY_SP, None, cm))
+    }
+
+    /// Create an error about the bytes `primary` of `source`, in a codemap of its own, eg)
+    /// `CompilerMessage::with_source_and_span_bytes("fn foo() {}", 3..6)`. The span is
+    /// underlined without a label, and the message itself is empty until it's set.
+    pub fn with_source_and_span_bytes(source: &str, primary: Range<usize>) -> CompilerMessage {
+        let (cm, spans) = anon_source_spans(source, &[primary]);
+        let span = spans[0];
+
+        let mut message = CompilerMessage::new(Level::Error, String::new(), span, None, cm);
+        message.span_label(span, None);
+        message
+    }
+
+    /// Render just the given (1-based) line of the primary span's file, with its
+    /// annotations, eg) for editor gutter decorations. Returns None if no label starts
+    /// on that line.
+    pub fn render_source_line_at(&self, line_number: usize) -> Option<Vec<Vec<StyledString>>> {
+        source_line_at(self, line_number)
+    }
+
+    /// The number of columns each character of the line containing `span.lo` takes up on
+    /// screen (eg, 2 for CJK characters, 0 for combining marks), for editors that draw their
+    /// own underlines
+    pub fn render_char_width_map(&self, span: Span) -> Vec<usize> {
+        char_width_map(self, span)
+    }
+
+    /// The message on one line without any source, for build logs that are searched by
+    /// pattern, eg) `foo.rs:3: error: Unresolved name [E0425]`
+    pub fn render_summary(&self) -> String {
+        if self.level == Level::Cancelled {
+            return String::new();
+        }
+        self.mark_emitted();
+
+        let location = match self.location_override {
+            Some((ref file, line, _)) => Some((file.clone(), line)),
+            None => self.file_and_line_for_span(self.primary_span),
+        };
+
+        let mut output = String::new();
+        if let Some((file, line)) = location {
+            output.push_str(&format!("{}:{}: ", file, line));
+        }
+        output.push_str(&format!("{}: {}", self.level.to_string(), self.primary_msg));
+        if let Some(ref code) = self.error_code {
+            output.push_str(&format!(" [{}]", code));
+        }
+        output
+    }
+
+    /// Dump the labels of the message and the annotations made from them, one per line,
+    /// without rendering anything, eg) when debugging why a message renders wrongly
+    pub fn render_debug_repr(&self) -> String {
+        debug_repr(self)
+    }
+
+    /// Render only the annotated source lines of the message, in the order their spans
+    /// appear in the codemap rather than with the primary file first
+    pub fn render_source_lines_sorted_by_span(&self) -> Vec<Vec<StyledString>> {
+        self.mark_emitted();
+        let mut buffer = StyledBuffer::new();
+        render_source_lines_sorted_by_span(self, &mut buffer);
+        buffer.render()
+    }
+
+    /// Render the message with the given strategy, eg) `HumanRenderer` or `JsonRenderer`
+    pub fn render_with(&self, r: &Renderer) -> String {
+        self.mark_emitted();
+        let mut output: Vec<u8> = vec![];
+
+        // Writing into a Vec can't fail
+        r.render_header(self, &mut output).unwrap();
+        r.render_source_block(self, &mut output).unwrap();
+        r.render_footer(self, &mut output).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Render the message and append it to `rope` one styled piece at a time, eg) straight
+    /// into an editor's buffer. The message is still laid out in a full `StyledBuffer`
+    /// first; only the copy into `rope` is done piece by piece.
+    pub fn render_to_rope(&self, rope: &mut RopeWriter) {
+        write_rope(render_succinct(self), rope);
+    }
+
+    /// Render the message and write it to `w`, with ANSI colors if `use_color` is set and
+    /// as plain text otherwise
+    pub fn render_to_color_printer(&self, w: &mut Write, use_color: bool) -> io::Result<()> {
+        if use_color {
+            write_ansi(render_succinct(self), self.level, w)
+        } else {
+            write_plain(render_succinct(self), w)
+        }
+    }
+
+    /// Render the message to a string with ANSI colors, eg) for a terminal that isn't
+    /// stderr
+    pub fn render_to_string_ansi(&self) -> String {
+        let mut output: Vec<u8> = vec![];
+
+        // Writing into a Vec can't fail
+        write_ansi(render_succinct(self), self.level, &mut output).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Write the message to stderr as plain text in a single `write_all`, so that messages
+    /// emitted from several threads at once don't get interleaved line by line. On POSIX,
+    /// writes of up to `PIPE_BUF` bytes to a pipe are delivered atomically.
+    pub fn render_to_stderr_atomic(&self) -> io::Result<()> {
+        self.render_to_writer_atomic(&mut io::stderr())
+    }
+
+    /// Like `render_to_stderr_atomic`, but writes to `w`. Cancelled and suppressed messages
+    /// aren't written, the same as with `render_all_to_stderr`.
+    pub fn render_to_writer_atomic(&self, w: &mut Write) -> io::Result<()> {
+        if self.level == Level::Cancelled || self.suppressed {
+            return Ok(());
+        }
+
+        let buf = render_to_string_lossy(self).into_bytes();
+        w.write_all(&buf)
+    }
+}
+
+/// Put `source` in a codemap of its own as `<anon>`, and turn the given byte ranges of it
+/// into spans, for messages about a string that doesn't come from a file
+pub fn anon_source_spans(source: &str,
+                         ranges: &[Range<usize>])
+                         -> (Rc<codemap::CodeMap>, Vec<Span>) {
+    let cm = Rc::new(codemap::CodeMap::new());
+    let file = cm.new_filemap_and_lines("<anon>", source);
+    let spans = ranges.iter()
+        .map(|range| {
+            Span {
+                lo: file.start_pos + BytePos(range.start as u32),
+                hi: file.start_pos + BytePos(range.end as u32),
+                expn_id: NO_EXPANSION,
+            }
+        })
+        .collect();
+    (cm, spans)
+}
+
+/// Kept in its own field rather than implementing `Drop` for `CompilerMessage` itself, so
+/// that the other fields can still be moved out of a message
+struct CancelGuard {
+    on_cancel: Option<Box<FnOnce()>>,
+    emitted: Cell<bool>,
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if !self.emitted.get() {
+            if let Some(f) = self.on_cancel.take() {
+                f();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for CompilerMessage {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "CompilerMessage({:?}: {})", self.level, self.primary_msg)
+    }
+}
+
+impl fmt::Display for CompilerMessage {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", render_to_string_lossy(self))
+    }
+}
+
+impl Error for CompilerMessage {}
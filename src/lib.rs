@@ -2,6 +2,10 @@
 #![feature(range_contains)]
 
 extern crate term;
+extern crate unicode_width;
+extern crate unicode_bidi;
+#[cfg(feature = "regex")]
+extern crate regex;
 
 use std::io::{self, Write};
 use std::rc::Rc;
@@ -15,12 +19,50 @@ use compiler_message::*;
 mod render_succinct;
 use render_succinct::*;
 
+mod render_hover;
+use render_hover::*;
+
+mod render_json;
+use render_json::*;
+
+mod render_github;
+use render_github::*;
+
+mod render_vscode;
+use render_vscode::*;
+
+mod renderer;
+use renderer::*;
+
+#[cfg(feature = "ariadne-style")]
+mod render_ariadne;
+
 mod styled_emit;
 use styled_emit::*;
 
+pub mod styles;
+
+pub mod prelude;
+
 mod codemap;
 use codemap::*;
 
+pub use codemap::{CodeMap, Span, SpanLabel, BytePos, NO_EXPANSION};
+pub use compiler_message::{CompilerMessage, DiagnosticResult, FixResult, LabelFormatter};
+pub use styled_buffer::{Level, Style, StyledString};
+pub use render_succinct::{render_succinct, set_old_school, line_annotations, Annotation,
+                          render_source_block, render_with_width, render_all_labels_flat,
+                          render_only_primary_span, render_with_codemap_source,
+                          render_with_elided_files};
+pub use render_hover::render_for_editor_hover;
+pub use render_json::render_compact_json;
+pub use render_github::render_as_github_annotation;
+pub use render_vscode::render_as_vscode_problem_matcher;
+pub use renderer::{Renderer, HumanRenderer, JsonRenderer, RopeWriter};
+pub use styled_emit::render_all_to_stderr;
+#[cfg(feature = "ariadne-style")]
+pub use render_ariadne::AriadneRenderer;
+
 
 
 trait CodeMapExtension {
@@ -266,4 +308,2637 @@ error: Not sure what this is [E123]
   |>
   => note: Are you sure you want to call it `vec`?
 "#[1..]);
-}
\ No newline at end of file
+}
+#[test]
+fn test_secondary_files_sorted() {
+    let file_text = r#"
+fn foo() {
+    vec.push(1);
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let zed = cm.new_filemap_and_lines("zed.rs", file_text);
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let bar = cm.new_filemap_and_lines("bar.rs", file_text);
+    let span_foo = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_zed = cm.span_substr(&zed, file_text, "vec", 0);
+    let span_bar = cm.span_substr(&bar, file_text, "vec", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Not sure what this is"),
+                                       span_foo,
+                                       None,
+                                       cm);
+
+    err.span_label(span_zed, Some(String::from("zed message")));
+    err.span_label(span_foo, Some(String::from("primary message")));
+    err.span_label(span_bar, Some(String::from("bar message")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Not sure what this is
+ --> foo.rs:3:4
+  |>
+3 |>    vec.push(1);
+  |>    ^^^ primary message
+  |>
+ ::: bar.rs
+  |>
+3 |>    vec.push(1);
+  |>    --- bar message
+  |>
+ ::: zed.rs
+  |>
+3 |>    vec.push(1);
+  |>    --- zed message
+"#[1..]);
+}
+
+#[test]
+fn test_suggestion() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.with_machine_applicable_suggestion(span_vec0, String::from("vec2"));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^ primary message
+  |>
+  => help: replace with `vec2`
+"#[1..]);
+}
+
+#[test]
+fn test_malformed_annotation_skipped() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+    let span_push = cm.span_substr(&foo, file_text, "push", 0);
+    let backwards = Span {
+        lo: span_push.hi,
+        hi: span_push.lo,
+        expn_id: NO_EXPANSION,
+    };
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.span_label(backwards, Some(String::from("backwards message")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^ primary message
+"#[1..]);
+}
+
+#[test]
+fn test_line_annotations() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.span_label(span_vec1, None);
+
+    let lines: Vec<_> = line_annotations(&err).collect();
+
+    assert_eq!(lines.len(), 1);
+    let (line_number, ref source, ref annotations) = lines[0];
+    assert_eq!(line_number, 3);
+    assert_eq!(&source[..], "    vec.push(vec.pop().unwrap());");
+    assert_eq!(annotations.len(), 2);
+    assert_eq!((annotations[0].start_col, annotations[0].end_col), (4, 7));
+    assert!(!annotations[0].is_primary);
+    assert_eq!((annotations[1].start_col, annotations[1].end_col), (13, 16));
+    assert!(annotations[1].is_primary);
+}
+
+#[test]
+fn test_into_vec_of_strings() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+
+    let lines: Vec<String> = err.into();
+
+    assert_eq!(lines,
+               vec!["error: Unresolved name",
+                    " --> foo.rs:3:13",
+                    "  |>",
+                    "3 |>    vec.push(vec.pop().unwrap());",
+                    "  |>             ^^^ primary message"]);
+}
+
+#[test]
+fn test_three_labels_hang_below() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_unwrap = cm.span_substr(&foo, file_text, "unwrap", 0);
+    let span_push = cm.span_substr(&foo, file_text, "vec.push", 0);
+    let span_pop_call = cm.span_substr(&foo, file_text, "vec.pop()", 0);
+    let span_pop = cm.span_substr(&foo, file_text, "pop", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Not sure what this is"),
+                                       span_unwrap,
+                                       None,
+                                       cm);
+
+    err.span_label(span_push, Some(String::from("first message")));
+    err.span_label(span_pop_call, Some(String::from("second message")));
+    err.span_label(span_pop, Some(String::from("third message")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Not sure what this is
+ --> foo.rs:3:23
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>    -------- ---------
+  |>    |        |   |
+  |>    |        |   third message
+  |>    |        second message
+  |>    first message
+"#[1..]);
+}
+
+#[test]
+fn test_wide_characters() {
+    let file_text = r#"
+fn foo() {
+    let s = "日本"; vec.pop();
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_str = cm.span_substr(&foo, file_text, "\"日本\"", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec, Some(String::from("primary message")));
+    err.span_label(span_str, None);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:18
+  |>
+3 |>    let s = "日本"; vec.pop();
+  |>            ------  ^^^ primary message
+"#[1..]);
+}
+
+#[test]
+fn test_snippet_context() {
+    let file_text = "fn main() { let x = 1; }";
+    let cm = Rc::new(CodeMap::new());
+    let mut err = CompilerMessage::new(Level::Warning,
+                                       String::from("unused variable"),
+                                       DUMMY_SP,
+                                       None,
+                                       cm.clone());
+
+    err.with_snippet_context("test.rs", file_text.to_string())
+        .with_context_file("unused.rs", vec![]);
+    assert!(err.snippet_file("other.rs").is_none());
+
+    let test = err.snippet_file("test.rs").unwrap();
+    let span_x = cm.span_substr(&test, file_text, "x", 0);
+    err.primary_span = span_x;
+    err.span_label(span_x, Some(String::from("declared here")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+warning: unused variable
+ --> test.rs:1:16
+  |>
+1 |>fn main() { let x = 1; }
+  |>                ^ declared here
+"#[1..]);
+}
+
+#[test]
+fn test_apply_suggestion() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+    err.with_machine_applicable_suggestion(span_vec0, String::from("vec2"));
+
+    let fixed = err.apply_suggestion(err.suggestions[0].span, &err.suggestions[0].replacement)
+        .unwrap();
+
+    assert_eq!(&fixed[..],
+               &r#"
+fn foo() {
+    vec.push(vec2.pop().unwrap());
+}
+"#[..]);
+}
+
+#[test]
+fn test_apply_suggestion_without_source() {
+    let cm = Rc::new(CodeMap::new());
+    let lib = cm.new_imported_filemap(String::from("lib.rs"),
+                                      24,
+                                      vec![BytePos(0), BytePos(11), BytePos(22)],
+                                      vec![]);
+    let span_bar = Span {
+        lo: lib.start_pos + BytePos(15),
+        hi: lib.start_pos + BytePos(18),
+        expn_id: NO_EXPANSION,
+    };
+
+    let err = CompilerMessage::new(Level::Error,
+                                   String::from("Unresolved name"),
+                                   span_bar,
+                                   None,
+                                   cm);
+
+    assert!(err.apply_suggestion(span_bar, "baz").is_none());
+}
+
+#[test]
+fn test_apply_suggestion_across_files() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let bar = cm.new_filemap_and_lines("bar.rs", file_text);
+    let span_foo = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_bar = cm.span_substr(&bar, file_text, "vec", 0);
+    let span_both = Span {
+        lo: span_foo.lo,
+        hi: span_bar.hi,
+        expn_id: NO_EXPANSION,
+    };
+
+    let err = CompilerMessage::new(Level::Error,
+                                   String::from("Unresolved name"),
+                                   span_foo,
+                                   None,
+                                   cm);
+
+    assert!(err.apply_suggestion(span_both, "vec2").is_none());
+}
+
+#[test]
+fn test_apply_suggestion_backwards_span() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 1);
+    let backwards = Span {
+        lo: span_vec1.lo,
+        hi: span_vec0.hi,
+        expn_id: NO_EXPANSION,
+    };
+
+    let err = CompilerMessage::new(Level::Error,
+                                   String::from("Unresolved name"),
+                                   span_vec0,
+                                   None,
+                                   cm);
+
+    assert!(err.apply_suggestion(backwards, "vec2").is_none());
+}
+
+#[test]
+fn test_sorted_by_span() {
+    let file_text = r#"
+fn foo() {
+    vec.push(1);
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let bar = cm.new_filemap_and_lines("bar.rs", file_text);
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_foo = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_bar = cm.span_substr(&bar, file_text, "vec", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Not sure what this is"),
+                                       span_foo,
+                                       None,
+                                       cm);
+
+    err.span_label(span_foo, Some(String::from("primary message")));
+    err.span_label(span_bar, Some(String::from("bar message")));
+
+    let text = make_string(err.render_source_lines_sorted_by_span());
+
+    // The files are separated the same way as when the primary file comes first
+    assert_eq!(&text[..],
+               &r#"
+ ::: bar.rs
+  |>
+3 |>    vec.push(1);
+  |>    --- bar message
+  |>
+ --> foo.rs:3:4
+  |>
+3 |>    vec.push(1);
+  |>    ^^^ primary message
+"#[1..]);
+}
+
+#[test]
+fn test_suppress_if_duplicate() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut seen = std::collections::HashSet::new();
+    let mut first = CompilerMessage::new(Level::Error,
+                                         String::from("Unresolved name"),
+                                         span_vec0,
+                                         None,
+                                         cm.clone());
+    let mut second = CompilerMessage::new(Level::Error,
+                                          String::from("Unresolved name"),
+                                          span_vec1,
+                                          None,
+                                          cm.clone());
+    let mut duplicate = CompilerMessage::new(Level::Error,
+                                             String::from("Unresolved name"),
+                                             span_vec0,
+                                             None,
+                                             cm);
+
+    assert!(!first.suppress_if_duplicate(&mut seen));
+    assert!(!second.suppress_if_duplicate(&mut seen));
+    assert!(duplicate.suppress_if_duplicate(&mut seen));
+    assert!(duplicate.suppressed);
+    assert_eq!(duplicate.level, Level::Error);
+
+    // A suppressed message can still be rendered by any of the renderers
+    duplicate.span_label(span_vec0, None);
+    let text = make_string(render_succinct(&duplicate));
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:4
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>    ^^^
+"#[1..]);
+    assert_eq!(duplicate.to_string(), text);
+    assert!(render_compact_json(&duplicate).starts_with("{\"level\":\"error\""));
+    assert!(render_for_editor_hover(&duplicate)
+                .starts_with("**error: Unresolved name (foo.rs:3:4)**"));
+}
+
+#[test]
+fn test_primary_wins_at_shared_column() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+    let span_pop_call = cm.span_substr(&foo, file_text, "vec.pop()", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.span_label(span_pop_call, Some(String::from("secondary message")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^------
+  |>             |
+  |>             secondary message
+  |>             primary message
+"#[1..]);
+}
+
+#[test]
+fn test_editor_hover() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+    let error_code = Some("E123".to_string());
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       error_code,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.span_label(span_vec1, Some(String::from("secondary message")));
+
+    let text = render_for_editor_hover(&err);
+
+    assert_eq!(&text[..],
+               &r#"
+**error: Unresolved name [E123] (foo.rs:3:13)**
+
+```
+    vec.push(vec.pop().unwrap());
+```
+
+- **vec**: primary message
+- **vec**: secondary message
+"#[1..]);
+}
+
+#[test]
+fn test_editor_hover_location() {
+    let cm = Rc::new(CodeMap::new());
+
+    // Without a span there's no location to show
+    let mut err = CompilerMessage::new(Level::Warning,
+                                       String::from("unused config key"),
+                                       DUMMY_SP,
+                                       None,
+                                       cm);
+    assert_eq!(render_for_editor_hover(&err), "**warning: unused config key**\n");
+
+    err.set_file_line_col(String::from("config.yml"), 3, 7);
+    assert_eq!(render_for_editor_hover(&err),
+               "**warning: unused config key (config.yml:3:7)**\n");
+}
+
+#[test]
+fn test_editor_hover_cancelled() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec = cm.span_substr(&foo, file_text, "vec", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec,
+                                       None,
+                                       cm);
+    err.span_label(span_vec, Some(String::from("not found")));
+    err.level = Level::Cancelled;
+
+    assert_eq!(render_for_editor_hover(&err), "");
+}
+
+#[test]
+fn test_file_line_col_override() {
+    let cm = Rc::new(CodeMap::new());
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("expected a mapping"),
+                                       DUMMY_SP,
+                                       None,
+                                       cm);
+    err.set_file_line_col(String::from("config.yml"), 3, 7);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: expected a mapping
+ --> config.yml:3:7
+"#[1..]);
+}
+
+#[test]
+fn test_file_and_line_for_span() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let err = CompilerMessage::new(Level::Error,
+                                   String::from("Unresolved name"),
+                                   DUMMY_SP,
+                                   None,
+                                   cm.clone());
+    assert_eq!(err.file_and_line_for_span(DUMMY_SP), None);
+
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+    assert_eq!(err.file_and_line_for_span(span_vec0),
+               Some((String::from("foo.rs"), 3)));
+}
+
+#[test]
+fn test_macro_expansion() {
+    let file_text = r#"
+fn foo() {
+    foo!(vec);
+}
+"#;
+    let macro_text = "vec.pop()\n";
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let macros = cm.new_filemap_and_lines("<foo macros>", macro_text);
+    let span_call = cm.span_substr(&foo, file_text, "foo!(vec)", 0);
+    let expn_id = cm.record_expansion(ExpnInfo {
+        call_site: span_call,
+        callee: NameAndSpan {
+            format: MacroBang(String::from("foo")),
+            allow_internal_unstable: false,
+            span: None,
+        },
+    });
+    let span_pop = Span { expn_id: expn_id, ..cm.span_substr(&macros, macro_text, "pop", 0) };
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("no method named `pop`"),
+                                       span_pop,
+                                       None,
+                                       cm);
+
+    err.span_label(span_pop, Some(String::from("primary message")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: no method named `pop`
+ --> <foo macros>:1:4
+  |>
+1 |>vec.pop()
+  |>    ^^^ primary message
+  |>
+ ::: foo.rs
+  |>
+3 |>    foo!(vec);
+  |>    --------- in this macro invocation
+  |>
+  => note: in expansion of foo!
+"#[1..]);
+}
+
+#[test]
+fn test_from_io_error() {
+    let e = io::Error::new(io::ErrorKind::NotFound, "No such file or directory");
+    let err = CompilerMessage::from_io_error(e, std::path::Path::new("src/missing.rs"));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: cannot read file 'src/missing.rs': No such file or directory
+"#[1..]);
+}
+
+#[test]
+fn test_source_block() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.note(String::from("this note is not shown"));
+
+    let msg = render_source_block(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^ primary message
+"#[1..]);
+}
+
+#[test]
+fn test_context_file() {
+    let cm = Rc::new(CodeMap::new());
+    let lib = cm.new_imported_filemap(String::from("lib.rs"),
+                                      24,
+                                      vec![BytePos(0), BytePos(11), BytePos(22)],
+                                      vec![]);
+    let span_bar = Span {
+        lo: lib.start_pos + BytePos(15),
+        hi: lib.start_pos + BytePos(18),
+        expn_id: NO_EXPANSION,
+    };
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_bar,
+                                       None,
+                                       cm);
+
+    err.span_label(span_bar, Some(String::from("primary message")));
+    err.with_context_file("lib.rs",
+                          vec![String::from("fn foo() {"),
+                               String::from("    bar();"),
+                               String::from("}")]);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> lib.rs:2:4
+  |>
+2 |>    bar();
+  |>    ^^^ primary message
+"#[1..]);
+}
+
+#[test]
+fn test_render_with_width() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("this is a rather long primary message")));
+    err.span_label(span_vec1, Some(String::from("secondary message")));
+
+    let msg = render_with_width(&err, 30);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>    ---      ^^^
+  |>    |        |
+  |>    |        this is a
+  |>    |        rather long
+  |>    |        primary
+  |>    |        message
+  |>    secondary message
+"#[1..]);
+}
+
+#[test]
+fn test_compact_json() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved \"name\""),
+                                       span_vec0,
+                                       Some(String::from("E0425")),
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.span_label(span_vec1, None);
+    err.with_machine_applicable_suggestion(span_vec0, String::from("vec2"));
+
+    assert_eq!(&render_compact_json(&err)[..],
+               concat!(r#"{"level":"error","message":"Unresolved \"name\"","#,
+                       r#""file":"foo.rs","line":3,"col":13,"#,
+                       r#""labels":[{"line":3,"col":13,"primary":true,"#,
+                       r#""label":"primary message"},"#,
+                       r#"{"line":3,"col":4,"primary":false,"label":null}],"#,
+                       r#""suggestions":[{"line":3,"col":13,"replacement":"vec2","#,
+                       r#""machine_applicable":true}],"code":"E0425"}"#,
+                       "\n"));
+}
+
+#[test]
+fn test_suppress_source_display() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("linking with `cc` failed"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")))
+        .suppress_source_display();
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: linking with `cc` failed
+"#[1..]);
+}
+
+#[test]
+fn test_render_with() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")))
+        .span_label(span_vec1, Some(String::from("secondary message")))
+        .note(String::from("a note"));
+
+    assert_eq!(err.render_with(&HumanRenderer), make_string(render_succinct(&err)));
+    assert_eq!(err.render_with(&JsonRenderer), render_compact_json(&err));
+}
+
+#[test]
+fn test_related() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut related = CompilerMessage::new(Level::Note,
+                                           String::from("first borrow here"),
+                                           span_vec1,
+                                           None,
+                                           cm.clone());
+    related.span_label(span_vec1, None);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("cannot borrow `vec` twice"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("second borrow")))
+        .with_related(vec![related]);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: cannot borrow `vec` twice
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^ second borrow
+    note: first borrow here
+     --> foo.rs:3:4
+      |>
+    3 |>    vec.push(vec.pop().unwrap());
+      |>    ^^^
+"#[1..]);
+    assert_eq!(err.render_with(&HumanRenderer), text);
+}
+
+#[test]
+fn test_into_string() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+
+    let text = format!("{}", String::from(err));
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^ primary message
+"#[1..]);
+}
+
+#[test]
+fn test_primary_line_number() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let err = CompilerMessage::from_io_error(io::Error::new(io::ErrorKind::NotFound, "missing"),
+                                             std::path::Path::new("foo.rs"));
+    assert_eq!(err.primary_line_number(), None);
+
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+    let err = CompilerMessage::new(Level::Error,
+                                   String::from("Unresolved name"),
+                                   span_vec0,
+                                   None,
+                                   cm);
+    assert_eq!(err.primary_line_number(), Some(3));
+}
+
+#[test]
+fn test_tab_expansion() {
+    let file_text = "
+fn foo() {
+\tvec.push(vec.pop().unwrap());
+}
+";
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.span_label(span_vec1, Some(String::from("secondary message")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:10
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>    ---      ^^^ primary message
+  |>    |
+  |>    secondary message
+"#[1..]);
+}
+
+#[test]
+fn test_all_labels_flat() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+    let span_brace = cm.span_substr(&foo, file_text, "}", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.span_label(span_vec1, None);
+    err.span_label(span_brace, Some(String::from("ends here")));
+
+    assert_eq!(render_all_labels_flat(&err),
+               vec![(3, 4, None, false),
+                    (3, 13, Some(String::from("primary message")), true),
+                    (4, 0, Some(String::from("ends here")), false)]);
+}
+
+#[test]
+fn test_diagnostic_result() {
+    fn read_config(path: &str) -> DiagnosticResult<String> {
+        let e = io::Error::new(io::ErrorKind::NotFound, "No such file or directory");
+        Err(CompilerMessage::from_io_error(e, std::path::Path::new(path)))
+    }
+
+    fn run() -> Result<String, Box<std::error::Error>> {
+        let config = read_config("missing.toml")?;
+        Ok(config)
+    }
+
+    let err = run().unwrap_err();
+
+    assert_eq!(&err.to_string()[..],
+               &r#"
+error: cannot read file 'missing.toml': No such file or directory
+"#[1..]);
+}
+
+#[test]
+fn test_empty_file_label() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+    let empty = cm.new_filemap_and_lines("empty.rs", "");
+    let span_empty = Span {
+        lo: empty.start_pos,
+        hi: empty.start_pos,
+        expn_id: NO_EXPANSION,
+    };
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.span_label(span_empty, Some(String::from("expected a definition here")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^ primary message
+  |>
+ ::: empty.rs
+  |>
+  |><empty file>
+"#[1..]);
+}
+
+#[cfg(feature = "ariadne-style")]
+#[test]
+fn test_ariadne_renderer() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       Some(String::from("E0425")),
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")))
+        .span_label(span_vec1, Some(String::from("secondary message")))
+        .note(String::from("a note"));
+
+    assert_eq!(&err.render_with(&AriadneRenderer)[..],
+               &r#"
+[E0425] Error: Unresolved name
+   ╭─[foo.rs:3:13]
+   │
+ 3 │     vec.push(vec.pop().unwrap());
+   │     ─┬─      ─┬─
+   │      │        ╰── primary message
+   │      ╰─────────── secondary message
+   │
+   │ Note: a note
+───╯
+"#[1..]);
+}
+
+#[cfg(feature = "ariadne-style")]
+#[test]
+fn test_ariadne_multiline_span() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+    let span_fn = Span {
+        lo: cm.span_substr(&foo, file_text, "fn", 0).lo,
+        hi: cm.span_substr(&foo, file_text, "}", 0).hi,
+        expn_id: NO_EXPANSION,
+    };
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")))
+        .span_label(span_fn, Some(String::from("in this function")));
+
+    assert_eq!(&err.render_with(&AriadneRenderer)[..],
+               &r#"
+Error: Unresolved name
+   ╭─[foo.rs:3:13]
+   │
+ 2 │ ╭─▶ fn foo() {
+ 3 │ │       vec.push(vec.pop().unwrap());
+   │ │                ─┬─
+   │ │                 ╰── primary message
+ 4 │ ├─▶ }
+   │ ╰─── in this function
+───╯
+"#[1..]);
+}
+
+#[test]
+fn test_render_only_primary_span() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.span_label(span_vec1, Some(String::from("secondary message")));
+
+    let msg = render_only_primary_span(&mut err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^ primary message
+"#[1..]);
+    assert_eq!(err.label_count(), (1, 1));
+}
+
+#[test]
+fn test_collapse_labels_beyond_column() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+    let span_unwrap = cm.span_substr(&foo, file_text, "unwrap", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")))
+        .span_label(span_vec1, Some(String::from("secondary message")))
+        .span_label(span_unwrap, Some(String::from("unwrap here")))
+        .collapse_labels_beyond_column(10);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>    ---      ^^^       ------
+  |>    |
+  |>    secondary message
+  |>
+  => note: ... and 2 labels beyond column 10 not shown
+"#[1..]);
+}
+
+#[test]
+fn test_suggestion_formatter() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")))
+        .with_machine_applicable_suggestion(span_vec0, String::from("  vec2 "))
+        .with_suggestion_formatter(Box::new(|s| s.trim().to_string()));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^ primary message
+  |>
+  => help: replace with `vec2`
+"#[1..]);
+
+    // The JSON output shows the same, formatted, replacement
+    let json = render_compact_json(&err);
+    assert!(json.contains(r#""suggestions":[{"line":3,"col":13,"replacement":"vec2","#));
+    assert_eq!(err.render_with(&JsonRenderer), json);
+}
+
+#[test]
+fn test_annotation_at_position() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")))
+        .span_label(span_vec1, Some(String::from("secondary message")));
+
+    let label = |line, col| {
+        err.annotation_at_position(line, col).and_then(|x| x.label.clone())
+    };
+    assert_eq!(label(3, 4), Some(String::from("secondary message")));
+    assert_eq!(label(3, 6), Some(String::from("secondary message")));
+    assert_eq!(label(3, 7), None);
+    assert_eq!(label(3, 13), Some(String::from("primary message")));
+    assert_eq!(label(2, 4), None);
+}
+
+#[test]
+fn test_github_annotation() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       Some(String::from("E0425")),
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("primary message")))
+        .span_label(span_vec1, Some(String::from("used here, 100% sure")));
+
+    assert_eq!(&render_as_github_annotation(&err)[..],
+               &r#"
+::error file=foo.rs,line=3,col=14::Unresolved name [E0425]
+::warning file=foo.rs,line=3,col=5::used here, 100%25 sure
+"#[1..]);
+
+    // An explicit location is reported the same way as one worked out from the span
+    err.set_file_line_col(String::from("foo.rs"), 3, 13);
+    assert!(render_as_github_annotation(&err)
+        .starts_with("::error file=foo.rs,line=3,col=14::Unresolved name [E0425]\n"));
+}
+
+#[test]
+fn test_label_format() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Warning,
+                                       String::from("borrowed twice"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("second borrow")))
+        .span_label(span_vec1, Some(String::from("first borrow")))
+        .with_label_format(|label, is_primary| {
+            if is_primary {
+                format!("{} [W01]", label)
+            } else {
+                format!("[lint] {}", label)
+            }
+        });
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+warning: borrowed twice
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>    ---      ^^^ second borrow [W01]
+  |>    |
+  |>    [lint] first borrow
+"#[1..]);
+}
+
+#[test]
+fn test_spans() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec1, Some(String::from("secondary message")));
+
+    assert_eq!(err.spans().collect::<Vec<_>>(), vec![span_vec0, span_vec1]);
+}
+
+#[test]
+fn test_rtl_support() {
+    let file_text = r#"
+fn foo() {
+    let שלום = vec;
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_shalom = cm.span_substr(&foo, file_text, "של", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_shalom,
+                                       None,
+                                       cm);
+
+    err.span_label(span_shalom, Some(String::from("primary message")))
+        .set_rtl_support(true);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    // The terminal shows the Hebrew run reversed, so its first two letters are displayed
+    // at the end of it
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:8
+  |>
+3 |>    let שלום = vec;
+  |>          ^^ primary message
+"#[1..]);
+}
+
+#[test]
+fn test_rightmost_label_inline() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+    let span_unwrap = cm.span_substr(&foo, file_text, "unwrap", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, None);
+    err.span_label(span_unwrap, Some(String::from("secondary message")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^       ------ secondary message
+"#[1..]);
+}
+
+#[test]
+fn test_render_with_codemap_source() {
+    let source = "let x = y + 1;\n";
+
+    let msg = render_with_codemap_source(source,
+                                         Level::Error,
+                                         "Unresolved name",
+                                         &[(8..9, Some("not found in this scope")),
+                                           (4..5, None)]);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> <anon>:1:8
+  |>
+1 |>let x = y + 1;
+  |>    -   ^ not found in this scope
+"#[1..]);
+}
+
+#[test]
+fn test_len() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec0,
+                                       None,
+                                       cm);
+    assert!(err.is_empty());
+
+    err.span_label(span_vec0, Some(String::from("primary message")));
+    err.span_label(span_vec1, None);
+    assert_eq!(err.len(), 2);
+    assert!(!err.is_empty());
+}
+
+#[test]
+fn test_annotation_past_end_of_line_clamped() {
+    let cm = Rc::new(CodeMap::new());
+    let lib = cm.new_imported_filemap(String::from("lib.rs"),
+                                      24,
+                                      vec![BytePos(0), BytePos(11), BytePos(22)],
+                                      vec![]);
+    let span_bar = Span {
+        lo: lib.start_pos + BytePos(15),
+        hi: lib.start_pos + BytePos(18),
+        expn_id: NO_EXPANSION,
+    };
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_bar,
+                                       None,
+                                       cm);
+
+    // The source we have for the file is shorter than the one the spans were made for
+    err.span_label(span_bar, Some(String::from("primary message")));
+    err.with_context_file("lib.rs",
+                          vec![String::from("fn foo() {"),
+                               String::from("    ba"),
+                               String::from("}")]);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> lib.rs:2:4
+  |>
+2 |>    ba
+  |>    ^^ primary message
+"#[1..]);
+}
+
+#[test]
+fn test_title_suffix() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Warning,
+                                       String::from("used `unwrap()` on a `Result`"),
+                                       span_vec0,
+                                       Some(String::from("W01")),
+                                       cm);
+
+    err.span_label(span_vec0, None)
+        .with_title_suffix("[clippy::restriction]");
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+warning: used `unwrap()` on a `Result` [clippy::restriction] [W01]
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^
+"#[1..]);
+}
+
+#[test]
+fn test_spanless() {
+    let mut err = CompilerMessage::spanless(Level::Error, String::from("no input filename given"));
+    err.note(String::from("pass a file to compile"));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: no input filename given
+  |>
+  => note: pass a file to compile
+"#[1..]);
+}
+
+#[test]
+fn test_annotation_at_end_of_line() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap())
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_call = cm.span_substr(&foo, file_text, "vec.push(vec.pop().unwrap())", 0);
+    let span_eol = Span {
+        lo: span_call.hi,
+        hi: span_call.hi,
+        expn_id: NO_EXPANSION,
+    };
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("expected `;`"),
+                                       span_eol,
+                                       None,
+                                       cm);
+
+    err.span_label(span_eol, Some(String::from("add `;` here")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: expected `;`
+ --> foo.rs:3:32
+  |>
+3 |>    vec.push(vec.pop().unwrap())
+  |>                                ^ add `;` here
+"#[1..]);
+}
+
+#[test]
+fn test_visible_column_range() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_push = cm.span_substr(&foo, file_text, "push", 0);
+    let span_pop = cm.span_substr(&foo, file_text, "pop", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_push,
+                                       None,
+                                       cm);
+
+    err.span_label(span_push, Some(String::from("primary message")));
+    err.span_label(span_pop, Some(String::from("out of view")));
+    err.with_visible_column_range(4..12);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:8
+  |>
+3 |>...vec.push...
+  |>       ^^^^- out of view
+  |>       |
+  |>       primary message
+"#[1..]);
+}
+
+#[test]
+fn test_visible_column_range_gap_line() {
+    let file_text = r#"
+fn foo() {
+    vec.push(1);
+    let	x = vec.pop().unwrap().unwrap_or_default().to_string().len();
+    vec.push(2);
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_push0 = cm.span_substr(&foo, file_text, "push", 0);
+    let span_push1 = cm.span_substr(&foo, file_text, "push", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_push0,
+                                       None,
+                                       cm);
+
+    err.span_label(span_push0, None);
+    err.span_label(span_push1, None);
+    err.with_visible_column_range(4..12);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    // The line in between is cut to the same columns, after its tab is expanded
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:8
+  |>
+3 |>...vec.push...
+  |>       ^^^^
+4 |>...let  x = ...
+5 |>...vec.push...
+  |>       ----
+"#[1..]);
+}
+
+#[test]
+fn test_padding() {
+    let mut err = CompilerMessage::spanless(Level::Error, String::from("no input filename given"));
+    err.with_padding(1);
+
+    let msg = render_succinct(&err);
+    assert_eq!(msg[0][0].style, Style::Padding);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+
+error: no input filename given
+
+"#[1..]);
+}
+
+#[test]
+fn test_render_to_writer_atomic() {
+    let mut err = CompilerMessage::with_source_and_span_bytes("fn foo() {}", 3..6);
+    err.primary_msg = String::from("unused function");
+
+    let mut output: Vec<u8> = vec![];
+    err.render_to_writer_atomic(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), err.to_string());
+
+    // Suppressed and cancelled messages aren't written at all
+    err.suppressed = true;
+    let mut output: Vec<u8> = vec![];
+    err.render_to_writer_atomic(&mut output).unwrap();
+    assert!(output.is_empty());
+
+    err.suppressed = false;
+    err.level = Level::Cancelled;
+    let mut output: Vec<u8> = vec![];
+    err.render_to_writer_atomic(&mut output).unwrap();
+    assert!(output.is_empty());
+}
+
+#[test]
+fn test_padding_render_with() {
+    let mut err = CompilerMessage::with_source_and_span_bytes("fn foo() {}", 3..6);
+    err.primary_msg = String::from("unused function");
+    err.note(String::from("a note")).with_padding(2);
+
+    assert_eq!(err.render_with(&HumanRenderer), make_string(render_succinct(&err)));
+}
+
+#[test]
+fn test_with_source_and_span_bytes() {
+    let mut err = CompilerMessage::with_source_and_span_bytes("fn foo() {}", 3..6);
+    err.primary_msg = String::from("unused function");
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: unused function
+ --> <anon>:1:3
+  |>
+1 |>fn foo() {}
+  |>   ^^^
+"#[1..]);
+}
+
+#[test]
+fn test_all_annotations_unlabeled() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec1,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, None);
+    err.span_label(span_vec1, None);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>    ---      ^^^
+"#[1..]);
+}
+
+#[test]
+fn test_render_with_elided_files() {
+    let file_text = r#"
+fn foo() {
+    vec.push(1);
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let zed = cm.new_filemap_and_lines("zed.rs", file_text);
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let bar = cm.new_filemap_and_lines("bar.rs", file_text);
+    let span_foo = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_zed = cm.span_substr(&zed, file_text, "vec", 0);
+    let span_bar = cm.span_substr(&bar, file_text, "vec", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Not sure what this is"),
+                                       span_foo,
+                                       None,
+                                       cm);
+
+    err.span_label(span_zed, Some(String::from("zed message")));
+    err.span_label(span_foo, Some(String::from("primary message")));
+    err.span_label(span_bar, Some(String::from("bar message")));
+
+    let msg = render_with_elided_files(&mut err, 2);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Not sure what this is
+ --> foo.rs:3:4
+  |>
+3 |>    vec.push(1);
+  |>    ^^^ primary message
+  |>
+ ::: bar.rs
+  |>
+3 |>    vec.push(1);
+  |>    --- bar message
+  |>
+  => note: 1 more file not shown
+"#[1..]);
+    assert!(err.notes.is_empty());
+}
+
+#[test]
+fn test_same_start_column() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 1);
+    let span_pop_call = cm.span_substr(&foo, file_text, "vec.pop()", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_pop_call,
+                                       None,
+                                       cm);
+
+    err.span_label(span_pop_call, Some(String::from("primary message")));
+    err.span_label(span_vec1, Some(String::from("secondary message")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ---^^^^^^
+  |>             |
+  |>             primary message
+  |>             secondary message
+"#[1..]);
+}
+
+#[test]
+fn test_set_codemap() {
+    let file_text = "fn foo() {}\n";
+
+    // Spans are only byte positions, so they can be made before their file is loaded
+    let span_foo = Span {
+        lo: BytePos(3),
+        hi: BytePos(6),
+        expn_id: NO_EXPANSION,
+    };
+    let mut err = CompilerMessage::new(Level::Warning,
+                                       String::from("unused function"),
+                                       span_foo,
+                                       None,
+                                       Rc::new(CodeMap::new()));
+    err.span_label(span_foo, None);
+
+    let cm = Rc::new(CodeMap::new());
+    cm.new_filemap_and_lines("foo.rs", file_text);
+    err.set_codemap(cm);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+warning: unused function
+ --> foo.rs:1:3
+  |>
+1 |>fn foo() {}
+  |>   ^^^
+"#[1..]);
+}
+
+#[test]
+fn test_whitespace_only_line() {
+    let mut err = CompilerMessage::with_source_and_span_bytes("    ", 1..3);
+    err.primary_msg = String::from("trailing whitespace");
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    // The source line is written out with escapes, as it's nothing but trailing spaces
+    assert_eq!(&text[..],
+               "error: trailing whitespace\n --> <anon>:1:1\n  |>\n1 |>    \n  |> ^^\n");
+}
+
+#[test]
+fn test_with_code_map_from_paths() {
+    // Unique to this test and process, so that concurrent runs don't share files
+    let dir = std::env::temp_dir();
+    let prefix = format!("error_reporter_{}_code_map_from_paths", std::process::id());
+    let foo_path = dir.join(format!("{}_foo.rs", prefix));
+    let bar_path = dir.join(format!("{}_bar.rs", prefix));
+    std::fs::write(&foo_path, "fn foo() {}\n").unwrap();
+    std::fs::write(&bar_path, "\nfn bar() {}\n").unwrap();
+
+    let err = CompilerMessage::with_code_map_from_paths(&[&foo_path, &bar_path]).unwrap();
+    let bar_name = bar_path.to_string_lossy().into_owned();
+    let bar = err.cm.get_filemap(&bar_name);
+    let span_bar = err.cm.span_substr(&bar, "\nfn bar() {}\n", "bar", 0);
+
+    assert_eq!(err.cm.files.borrow().len(), 2);
+    assert_eq!(err.file_and_line_for_span(span_bar), Some((bar_name, 2)));
+
+    let missing = dir.join(format!("{}_missing.rs", prefix));
+    assert!(CompilerMessage::with_code_map_from_paths(&[&foo_path, &missing]).is_err());
+
+    std::fs::remove_file(&foo_path).unwrap();
+    std::fs::remove_file(&bar_path).unwrap();
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_annotate_from_regex() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_push = cm.span_substr(&foo, file_text, "push", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_push,
+                                       None,
+                                       cm);
+
+    err.span_label(span_push, None);
+    err.annotate_from_regex(span_push, &regex::Regex::new(r"\bvec\b").unwrap(), None);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:8
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>    --- ^^^^ ---
+"#[1..]);
+}
+
+#[test]
+fn test_on_cancel() {
+    use std::cell::Cell;
+
+    let cancelled = Rc::new(Cell::new(0));
+
+    let mut err = CompilerMessage::spanless(Level::Error, String::from("suppressed"));
+    let counter = cancelled.clone();
+    err.on_cancel(Box::new(move || counter.set(counter.get() + 1)));
+    drop(err);
+    assert_eq!(cancelled.get(), 1);
+
+    let mut err = CompilerMessage::spanless(Level::Error, String::from("emitted"));
+    let counter = cancelled.clone();
+    err.on_cancel(Box::new(move || counter.set(counter.get() + 1)));
+    render_succinct(&err);
+    drop(err);
+    assert_eq!(cancelled.get(), 1);
+
+    // Every renderer counts as emitting the message, not just render_succinct
+    let mut err = CompilerMessage::spanless(Level::Error, String::from("as json"));
+    let counter = cancelled.clone();
+    err.on_cancel(Box::new(move || counter.set(counter.get() + 1)));
+    render_compact_json(&err);
+    drop(err);
+    assert_eq!(cancelled.get(), 1);
+
+    let mut err = CompilerMessage::spanless(Level::Error, String::from("as summary"));
+    let counter = cancelled.clone();
+    err.on_cancel(Box::new(move || counter.set(counter.get() + 1)));
+    err.render_summary();
+    drop(err);
+    assert_eq!(cancelled.get(), 1);
+
+    // Fields can be moved out of a message, and dropping the rest still cancels
+    {
+        let mut err = CompilerMessage::spanless(Level::Error, String::from("moved from"));
+        let counter = cancelled.clone();
+        err.on_cancel(Box::new(move || counter.set(counter.get() + 1)));
+        let notes = err.notes;
+        assert!(notes.is_empty());
+    }
+    assert_eq!(cancelled.get(), 2);
+}
+
+#[test]
+fn test_annotation_covers_whole_line() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_line = cm.span_substr(&foo, file_text, "    vec.push(vec.pop().unwrap());", 0);
+    let span_fn = cm.span_substr(&foo, file_text, "fn foo() {", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("mismatched types"),
+                                       span_line,
+                                       None,
+                                       cm);
+
+    err.span_label(span_line, Some(String::from("expected `()`")));
+    err.span_label(span_fn, Some(String::from("in this function")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: mismatched types
+ --> foo.rs:3:0
+  |>
+2 |>fn foo() {
+  |>---------- in this function
+3 |>    vec.push(vec.pop().unwrap());
+  |>^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^ expected `()`
+"#[1..]);
+}
+
+#[test]
+fn test_filter_labels() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec0 = cm.span_substr(&foo, file_text, "vec", 0);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec1,
+                                       None,
+                                       cm);
+
+    err.span_label(span_vec0, Some(String::from("secondary message")));
+    err.span_label(span_vec1, Some(String::from("primary message")));
+    err.filter_labels(|label| label.is_primary);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^ primary message
+"#[1..]);
+}
+
+#[test]
+fn test_multibyte_before_annotation() {
+    let file_text = "résumé();\n";
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_paren = cm.span_substr(&foo, file_text, "(", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("expected function"),
+                                       span_paren,
+                                       None,
+                                       cm);
+
+    err.span_label(span_paren, Some(String::from("call here")));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    // `é` is two bytes, but the `^` goes by characters: `(` is the 7th character
+    assert_eq!(&text[..],
+               &r#"
+error: expected function
+ --> foo.rs:1:6
+  |>
+1 |>résumé();
+  |>      ^ call here
+"#[1..]);
+}
+
+#[test]
+fn test_machine_applicable_fix() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 1);
+    let span_pop = cm.span_substr(&foo, file_text, "pop", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec1,
+                                       None,
+                                       cm);
+    err.with_machine_applicable_fix(span_vec1, Box::new(|src| format!("{}2", src)));
+    err.with_machine_applicable_fix(span_pop, Box::new(|src| src.to_uppercase()));
+    assert!(err.suggestions.is_empty());
+
+    assert_eq!(err.apply_machine_applicable_fix(),
+               FixResult::Applied(String::from(r#"
+fn foo() {
+    vec.push(vec2.pop().unwrap());
+}
+"#)));
+    assert_eq!(&err.suggestions[0].replacement[..], "vec2");
+
+    // A second fix doesn't replace the first, and is applied on top of it
+    assert_eq!(err.apply_machine_applicable_fix(),
+               FixResult::Applied(String::from(r#"
+fn foo() {
+    vec.push(vec2.POP().unwrap());
+}
+"#)));
+    assert_eq!(&err.suggestions[1].replacement[..], "POP");
+    assert_eq!(err.apply_machine_applicable_fix(), FixResult::Exhausted);
+}
+
+#[test]
+fn test_machine_applicable_fix_without_source() {
+    let cm = Rc::new(CodeMap::new());
+    let lib = cm.new_imported_filemap(String::from("lib.rs"),
+                                      24,
+                                      vec![BytePos(0), BytePos(11), BytePos(22)],
+                                      vec![]);
+    let span_bar = Span {
+        lo: lib.start_pos + BytePos(15),
+        hi: lib.start_pos + BytePos(18),
+        expn_id: NO_EXPANSION,
+    };
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_bar,
+                                       None,
+                                       cm);
+    err.with_machine_applicable_fix(span_bar, Box::new(|src| format!("{}2", src)));
+
+    // The fix can't be applied, but isn't lost or shown as a suggestion either
+    assert_eq!(err.apply_machine_applicable_fix(), FixResult::Failed);
+    assert_eq!(err.machine_applicable_fixes.len(), 1);
+    assert!(err.suggestions.is_empty());
+}
+
+#[test]
+fn test_hanging_label_columns() {
+    let file_text = r#"
+fn foo() {
+    let x = vec.pop();
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_x = cm.span_substr(&foo, file_text, "x", 0);
+    let span_pop = cm.span_substr(&foo, file_text, "vec.pop()", 0);
+    let span_let = cm.span_substr(&foo, file_text, "let x = vec.pop();", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_x,
+                                       None,
+                                       cm);
+
+    err.span_label(span_x, Some(String::from("x")));
+    err.span_label(span_pop, Some(String::from("pop")));
+    err.span_label(span_let, None);
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    // The connectors and labels line up with the start of their spans, at columns 8 and 12
+    // of the source line
+    assert_eq!(&text[..],
+               &r#"
+error: Unresolved name
+ --> foo.rs:3:8
+  |>
+3 |>    let x = vec.pop();
+  |>    ----^-------------
+  |>        |   |
+  |>        |   pop
+  |>        x
+"#[1..]);
+}
+
+#[test]
+fn test_without_header() {
+    let mut err = CompilerMessage::with_source_and_span_bytes("fn foo() {}", 3..6);
+    err.primary_msg = String::from("unused function");
+    err.without_header();
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+ --> <anon>:1:3
+  |>
+1 |>fn foo() {}
+  |>   ^^^
+"#[1..]);
+}
+
+#[test]
+fn test_empty_primary_file() {
+    let mut err = CompilerMessage::with_source_and_span_bytes("", 0..0);
+    err.primary_msg = String::from("expected `fn main`");
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    // There is no line to point at, so only the file is named and the source is a placeholder
+    assert_eq!(&text[..],
+               &r#"
+error: expected `fn main`
+ --> <anon>
+  |>
+  |><empty file>
+"#[1..]);
+
+    // The other output formats cope too, but have no line to point at
+    assert_eq!(&render_compact_json(&err)[..],
+               concat!(r#"{"level":"error","message":"expected `fn main`","#,
+                       r#""file":null,"line":null,"col":null,"#,
+                       r#""labels":[{"line":null,"col":null,"primary":true,"label":null}],"#,
+                       r#""suggestions":[],"code":null}"#,
+                       "\n"));
+    assert_eq!(&render_as_github_annotation(&err)[..], "::error::expected `fn main`\n");
+    assert_eq!(err.render_with(&HumanRenderer), text);
+    #[cfg(feature = "ariadne-style")]
+    assert_eq!(&err.render_with(&AriadneRenderer)[..],
+               "Error: expected `fn main`\n   ╭─[<anon>]\n   │\n 0 │\n   │ ─\n───╯\n");
+}
+
+#[test]
+fn test_render_char_width_map() {
+    let file_text = "let 名前 = \"e\u{301}\";\n";
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_name = cm.span_substr(&foo, file_text, "名前", 0);
+
+    let err = CompilerMessage::new(Level::Error,
+                                   String::from("Unresolved name"),
+                                   span_name,
+                                   None,
+                                   cm);
+
+    assert_eq!(err.render_char_width_map(span_name),
+               vec![1, 1, 1, 1, 2, 2, 1, 1, 1, 1, 1, 0, 1, 1]);
+}
+
+#[test]
+fn test_render_to_rope() {
+    struct Pieces(Vec<(String, Style)>);
+    impl RopeWriter for Pieces {
+        fn append(&mut self, text: &str, style: Style) {
+            self.0.push((String::from(text), style));
+        }
+    }
+
+    let mut err = CompilerMessage::with_source_and_span_bytes("fn foo() {}", 3..6);
+    err.primary_msg = String::from("unused function");
+
+    let mut rope = Pieces(vec![]);
+    err.render_to_rope(&mut rope);
+
+    let text: String = rope.0.iter().map(|&(ref text, _)| &text[..]).collect();
+    assert_eq!(text, err.to_string());
+    assert!(rope.0.contains(&(String::from("^^^"), Style::UnderlinePrimary)));
+}
+
+#[test]
+fn test_render_summary() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec1,
+                                       Some(String::from("E0425")),
+                                       cm);
+    err.span_label(span_vec1, Some(String::from("primary message")));
+
+    assert_eq!(err.render_summary(), "foo.rs:3: error: Unresolved name [E0425]");
+
+    let spanless = CompilerMessage::spanless(Level::Warning, String::from("no input"));
+    assert_eq!(spanless.render_summary(), "warning: no input");
+}
+
+#[test]
+fn test_non_overlapping_labels() {
+    let msg = render_with_codemap_source("xs = ys + zs;\n",
+                                         Level::Error,
+                                         "mismatched types",
+                                         &[(5..7, Some("expected `u32`")),
+                                           (0..2, Some("declared here")),
+                                           (10..12, Some("found `f64`"))]);
+    let text = make_string(msg);
+
+    // The rightmost label still fits at the end of the underline row
+    assert_eq!(&text[..],
+               &r#"
+error: mismatched types
+ --> <anon>:1:5
+  |>
+1 |>xs = ys + zs;
+  |>--   ^^   -- found `f64`
+  |>|    |
+  |>|    expected `u32`
+  |>declared here
+"#[1..]);
+}
+
+#[test]
+fn test_render_to_color_printer() {
+    let mut err = CompilerMessage::with_source_and_span_bytes("fn foo() {}", 3..6);
+    err.primary_msg = String::from("unused function");
+
+    let mut plain = vec![];
+    err.render_to_color_printer(&mut plain, false).unwrap();
+    assert_eq!(String::from_utf8(plain).unwrap(), err.to_string());
+
+    let mut colored = vec![];
+    err.render_to_color_printer(&mut colored, true).unwrap();
+    let colored = String::from_utf8(colored).unwrap();
+    assert!(colored.starts_with("\x1b[31;1merror\x1b[0m\x1b[1m: unused function\x1b[0m\n"));
+    assert!(colored.contains("\x1b[31;1m^^^\x1b[0m"));
+}
+
+#[test]
+fn test_render_to_string_ansi() {
+    let mut err = CompilerMessage::with_source_and_span_bytes("fn foo() {}", 3..6);
+    err.primary_msg = String::from("unused function");
+
+    let text = err.render_to_string_ansi();
+    assert!(text.starts_with("\x1b[31;1merror\x1b[0m\x1b[1m: unused function\x1b[0m\n"));
+    assert!(text.contains("\x1b[31;1m^^^\x1b[0m"));
+
+    // The same as writing with colors to a printer
+    let mut colored = vec![];
+    err.render_to_color_printer(&mut colored, true).unwrap();
+    assert_eq!(text, String::from_utf8(colored).unwrap());
+}
+
+#[test]
+fn test_render_source_line_at() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 1);
+    let span_fn = cm.span_substr(&foo, file_text, "fn foo() {", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec1,
+                                       None,
+                                       cm);
+    err.span_label(span_vec1, Some(String::from("primary message")));
+    err.span_label(span_fn, None);
+
+    let text = make_string(err.render_source_line_at(3).unwrap());
+
+    assert_eq!(&text[..],
+               &r#"
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^ primary message
+"#[1..]);
+    assert!(err.render_source_line_at(4).is_none());
+}
+
+#[test]
+fn test_label_formatter() {
+    struct Severity;
+    impl LabelFormatter for Severity {
+        fn format(&self, label: &str, is_primary: bool) -> String {
+            if is_primary {
+                format!("error: {}", label)
+            } else {
+                format!("info: {}", label)
+            }
+        }
+    }
+
+    let file_text = "xs = ys;\n";
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_xs = cm.span_substr(&foo, file_text, "xs", 0);
+    let span_ys = cm.span_substr(&foo, file_text, "ys", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("mismatched types"),
+                                       span_ys,
+                                       None,
+                                       cm);
+    err.span_label(span_xs, Some(String::from("declared here")));
+    err.span_label(span_ys, Some(String::from("expected `u32`")));
+    err.with_label_formatter(Box::new(Severity));
+
+    let msg = render_succinct(&err);
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: mismatched types
+ --> foo.rs:1:5
+  |>
+1 |>xs = ys;
+  |>--   ^^ error: expected `u32`
+  |>|
+  |>info: declared here
+"#[1..]);
+}
+
+#[test]
+fn test_multiline_span_minimized() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_body = cm.span_substr(&foo, file_text, "{\n    vec.push(vec.pop().unwrap());\n}", 0);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("mismatched types"),
+                                       span_body,
+                                       None,
+                                       cm);
+
+    err.span_label(span_body, Some(String::from("expected `()`")));
+
+    let msg = render_succinct(&err);
+
+    // Only the first character is underlined, and the source isn't highlighted
+    assert!(msg[3].iter().all(|s| s.style != Style::Highlight));
+    let text = make_string(msg);
+
+    assert_eq!(&text[..],
+               &r#"
+error: mismatched types
+ --> foo.rs:2:9
+  |>
+2 |>fn foo() {
+  |>         ^ expected `()`
+"#[1..]);
+}
+
+#[test]
+fn test_vscode_problem_matcher() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let span_vec1 = cm.span_substr(&foo, file_text, "vec", 1);
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec1,
+                                       Some(String::from("E0425")),
+                                       cm);
+    err.span_label(span_vec1, Some(String::from("primary message")));
+
+    let text = render_as_vscode_problem_matcher(&err);
+
+    assert_eq!(&text[..],
+               &r#"
+error[E0425]: Unresolved name
+  --> foo.rs:3:14
+"#[1..]);
+
+    // An explicit location is reported the same way as one worked out from the span
+    err.set_file_line_col(String::from("foo.rs"), 3, 13);
+    assert_eq!(render_as_vscode_problem_matcher(&err), text);
+}
+
+#[test]
+fn test_render_debug_repr() {
+    let mut err = CompilerMessage::with_source_and_span_bytes("fn foo() {}", 3..6);
+    err.primary_msg = String::from("unused function");
+
+    let text = err.render_debug_repr();
+
+    assert!(text.starts_with("CompilerMessage(Error: unused function)\nspan labels:\n"));
+    assert!(text.contains("is_primary: true, label: None }\nfile <anon>:\n    line 1:\n"));
+    assert!(text.ends_with("Annotation { start_col: 3, end_col: 6, is_primary: true, \
+                            is_minimized: false, label: None }\n"));
+}
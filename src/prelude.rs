@@ -0,0 +1,5 @@
+//! The types needed to build and render most messages, for glob importing with
+//! `use error_reporter::prelude::*;`
+
+pub use {CompilerMessage, DiagnosticResult, Level, Style, StyledString};
+pub use {CodeMap, Span, SpanLabel};
@@ -0,0 +1,393 @@
+use std::cmp;
+use std::io::{self, Write};
+
+use styled_buffer::*;
+use compiler_message::*;
+use render_succinct::{file_annotations, all_span_labels, get_source_line, display_column,
+                      expand_tabs, Annotation};
+use renderer::{Renderer, write_plain};
+
+/// Output in the style of the `ariadne` crate: box-drawing characters for the gutter, and
+/// labels connected to their spans by arrows, eg)
+///
+/// ```text
+/// Error: Unresolved name
+///    ╭─[foo.rs:3:13]
+///    │
+///  3 │     vec.push(vec.pop().unwrap());
+///    │     ─┬─      ─┬─
+///    │      │        ╰── primary message
+///    │      ╰─────────── secondary message
+/// ───╯
+/// ```
+///
+/// Labels whose spans run over several lines are drawn in a margin to the left of the
+/// source instead, as an arrow from the line the span starts on down to the line it ends
+/// on, eg)
+///
+/// ```text
+///  2 │ ╭─▶ fn foo() {
+///  3 │ │       vec.push(vec.pop().unwrap());
+///    │ │                ─┬─
+///    │ │                 ╰── primary message
+///  4 │ ├─▶ }
+///    │ ╰─── in this function
+/// ```
+pub struct AriadneRenderer;
+
+impl Renderer for AriadneRenderer {
+    fn render_header(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()> {
+        // eg) [E0425] Error: Unresolved name
+        if let Some(ref code) = msg.error_code {
+            try!(write!(buf, "[{}] ", code));
+        }
+        let level = msg.level.to_string();
+        let mut chars = level.chars();
+        if let Some(first) = chars.next() {
+            try!(write!(buf, "{}{}", first.to_uppercase().collect::<String>(), chars.as_str()));
+        }
+        try!(write!(buf, ": {}", msg.primary_msg));
+        if let Some(ref suffix) = msg.title_suffix {
+            try!(write!(buf, " {}", suffix));
+        }
+        writeln!(buf, "")
+    }
+
+    fn render_source_block(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()> {
+        if msg.suppress_source {
+            return Ok(());
+        }
+
+        let files = file_annotations(msg);
+        let gutter_width = gutter_width(msg);
+        let mut buffer = StyledBuffer::new();
+
+        for (file_idx, &(ref file, ref lines)) in files.iter().enumerate() {
+            if lines.is_empty() {
+                continue;
+            }
+
+            // Where in the file we're looking: the primary span if it's in this file,
+            // otherwise the first annotation
+            let primary_lo = msg.cm.lookup_char_pos(msg.primary_span.lo);
+            let location = if primary_lo.file.name == file.name {
+                (primary_lo.line, primary_lo.col.0)
+            } else {
+                (lines[0].0, lines[0].2.first().map(|a| a.start_col).unwrap_or(0))
+            };
+
+            let row = buffer.num_lines();
+            let border = if file_idx == 0 { "╭─[" } else { "├─[" };
+            buffer.puts(row, gutter_width + 2, border, Style::LineNumber);
+            // Files without lines (eg, empty files) have nowhere in them to point at
+            let location = if location.0 == 0 {
+                file.name.clone()
+            } else {
+                format!("{}:{}:{}", file.name, location.0, location.1)
+            };
+            buffer.append(row, &location, Style::LineAndColumn);
+            buffer.append(row, "]", Style::LineNumber);
+            put_gutter(&mut buffer, row + 1, gutter_width, None);
+
+            // The lines that multi-line spans end on are shown too, even if nothing else
+            // on them is labeled
+            let multiline = multiline_labels(msg, &file.name);
+            let mut lines = lines.clone();
+            for label in &multiline {
+                if !lines.iter().any(|&(n, _, _)| n == label.end_line) {
+                    let source_string = get_source_line(msg, file, label.end_line - 1);
+                    lines.push((label.end_line, source_string.to_string(), vec![]));
+                }
+            }
+            lines.sort_by_key(|&(n, _, _)| n);
+
+            let margin_width = if multiline.is_empty() {
+                0
+            } else {
+                2 * multiline.len() + 2
+            };
+
+            // The rows each multi-line span starts, ends and has its label on, to draw
+            // its arrow through once all of the rows are in place
+            let mut start_rows = vec![0; multiline.len()];
+            let mut end_rows = vec![0; multiline.len()];
+            let mut label_rows = vec![None; multiline.len()];
+
+            for &(line_number, ref source_string, ref annotations) in &lines {
+                let source_row = buffer.num_lines();
+                for (i, label) in multiline.iter().enumerate() {
+                    if label.start_line == line_number {
+                        start_rows[i] = source_row;
+                    }
+                    if label.end_line == line_number {
+                        end_rows[i] = source_row;
+                    }
+                }
+
+                // The multi-line spans are drawn in the margin, so leave out the single
+                // character their annotations were shortened to
+                let annotations: Vec<Annotation> = annotations.iter()
+                    .filter(|a| !a.is_minimized)
+                    .cloned()
+                    .collect();
+                render_ariadne_line(&mut buffer,
+                                    gutter_width,
+                                    margin_width,
+                                    line_number,
+                                    source_string,
+                                    &annotations);
+
+                for (i, label) in multiline.iter().enumerate() {
+                    if label.end_line != line_number {
+                        continue;
+                    }
+                    if let Some(ref text) = label.label {
+                        let label_row = buffer.num_lines();
+                        put_gutter(&mut buffer, label_row, gutter_width, None);
+                        buffer.puts(label_row,
+                                    gutter_width + 4 + margin_width + 1,
+                                    text,
+                                    label_style(label.is_primary));
+                        label_rows[i] = Some(label_row);
+                    }
+                }
+            }
+
+            for (i, label) in multiline.iter().enumerate() {
+                draw_margin_arrow(&mut buffer,
+                                  gutter_width + 4,
+                                  margin_width,
+                                  2 * i,
+                                  label.is_primary,
+                                  start_rows[i],
+                                  end_rows[i],
+                                  label_rows[i]);
+            }
+        }
+
+        write_plain(buffer.render(), buf)
+    }
+
+    fn render_footer(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()> {
+        let has_source = !msg.suppress_source && !file_annotations(msg).is_empty();
+        let gutter_width = gutter_width(msg);
+
+        // Notes and suggestions go inside the box, if there is one
+        let prefix = if has_source {
+            format!(" {} │ ", " ".repeat(gutter_width))
+        } else {
+            String::new()
+        };
+        if has_source && (!msg.notes.is_empty() || !msg.suggestions.is_empty()) {
+            try!(writeln!(buf, " {} │", " ".repeat(gutter_width)));
+        }
+        for note in &msg.notes {
+            try!(writeln!(buf, "{}Note: {}", prefix, note));
+        }
+        for suggestion in &msg.suggestions {
+            try!(writeln!(buf,
+                          "{}Help: replace with `{}`",
+                          prefix,
+                          msg.formatted_replacement(suggestion)));
+        }
+
+        if has_source {
+            try!(writeln!(buf, "{}╯", "─".repeat(gutter_width + 2)));
+        }
+        Ok(())
+    }
+}
+
+/// The number of columns needed for the largest line number that will be shown
+fn gutter_width(msg: &CompilerMessage) -> usize {
+    file_annotations(msg)
+        .iter()
+        .flat_map(|&(ref file, ref lines)| {
+            let end_lines = multiline_labels(msg, &file.name).into_iter().map(|x| x.end_line);
+            lines.iter().map(|&(n, _, _)| n).chain(end_lines).collect::<Vec<_>>()
+        })
+        .map(|n| n.to_string().len())
+        .max()
+        .unwrap_or(1)
+}
+
+/// A label whose span starts and ends on different lines of the same file
+struct MultilineLabel {
+    /// The (1-based) lines the span starts and ends on
+    start_line: usize,
+    end_line: usize,
+
+    is_primary: bool,
+    label: Option<String>,
+}
+
+/// The labels of the message in `file_name` that span more than one line, in the order
+/// they start in
+fn multiline_labels(msg: &CompilerMessage, file_name: &str) -> Vec<MultilineLabel> {
+    all_span_labels(msg)
+        .into_iter()
+        .filter(|x| x.as_annotation(&msg.cm).is_some())
+        .filter_map(|span_label| {
+            let lo = msg.cm.lookup_char_pos(span_label.span.lo);
+            let hi = msg.cm.lookup_char_pos(span_label.span.hi);
+            if lo.file.name != file_name || hi.file.name != file_name || lo.line == hi.line {
+                return None;
+            }
+            let is_primary = span_label.is_primary;
+            Some(MultilineLabel {
+                start_line: lo.line,
+                end_line: hi.line,
+                is_primary: is_primary,
+                label: span_label.label.map(|label| msg.formatted_label(&label, is_primary)),
+            })
+        })
+        .collect()
+}
+
+fn underline_style(is_primary: bool) -> Style {
+    if is_primary {
+        Style::UnderlinePrimary
+    } else {
+        Style::UnderlineSecondary
+    }
+}
+
+fn label_style(is_primary: bool) -> Style {
+    if is_primary {
+        Style::LabelPrimary
+    } else {
+        Style::LabelSecondary
+    }
+}
+
+/// Draw the arrow of a multi-line span in column `col` of the margin: from the row of the
+/// line it starts on, down past the line it ends on, and across to its label if it has one
+fn draw_margin_arrow(buffer: &mut StyledBuffer,
+                     offset: usize,
+                     margin_width: usize,
+                     col: usize,
+                     is_primary: bool,
+                     start_row: usize,
+                     end_row: usize,
+                     label_row: Option<usize>) {
+    let style = underline_style(is_primary);
+    let last_row = label_row.unwrap_or(end_row);
+
+    for row in start_row + 1..last_row {
+        buffer.putc(row, offset + col, '│', style);
+    }
+
+    // eg) `╭─▶` where the span starts and `├─▶` where it ends
+    let end_corner = if label_row.is_some() { '├' } else { '╰' };
+    for &(row, corner) in &[(start_row, '╭'), (end_row, end_corner)] {
+        buffer.putc(row, offset + col, corner, style);
+        for c in col + 1..margin_width - 2 {
+            buffer.putc(row, offset + c, '─', style);
+        }
+        buffer.putc(row, offset + margin_width - 2, '▶', style);
+    }
+
+    if let Some(row) = label_row {
+        buffer.putc(row, offset + col, '╰', style);
+        for c in col + 1..margin_width {
+            buffer.putc(row, offset + c, '─', style);
+        }
+    }
+}
+
+/// Draw the gutter at the start of a row, with the line number if there is one
+///
+/// eg) ` 3 │` or `   │`
+fn put_gutter(buffer: &mut StyledBuffer,
+              row: usize,
+              gutter_width: usize,
+              line_number: Option<usize>) {
+    let gutter = match line_number {
+        Some(n) => format!(" {:>width$} │", n, width = gutter_width),
+        None => format!(" {} │", " ".repeat(gutter_width)),
+    };
+    buffer.puts(row, 0, &gutter, Style::LineNumber);
+}
+
+fn render_ariadne_line(buffer: &mut StyledBuffer,
+                       gutter_width: usize,
+                       margin_width: usize,
+                       line_number: usize,
+                       source_string: &str,
+                       annotations: &[Annotation]) {
+    let offset = gutter_width + 4 + margin_width;
+
+    // The source line itself
+    let row = buffer.num_lines();
+    put_gutter(buffer, row, gutter_width, Some(line_number));
+    let (expanded_source, _) = expand_tabs(source_string);
+    buffer.puts(row, offset, &expanded_source, Style::Quotation);
+
+    // Like the succinct renderer, skip annotations that run backwards and cut the others
+    // off at the end of the line
+    let source_len = source_string.chars().count();
+    let annotations: Vec<Annotation> = annotations.iter()
+        .filter(|a| a.start_col <= a.end_col)
+        .map(|a| {
+            let mut a = a.clone();
+            a.start_col = cmp::min(a.start_col, source_len);
+            a.end_col = cmp::min(a.end_col, source_len);
+            a
+        })
+        .collect();
+    if annotations.is_empty() {
+        return;
+    }
+
+    // Underline each span, with a `┬` in the middle of the labeled ones for their arrow
+    // to start from. Primary spans are drawn last so that they win where spans overlap.
+    let underline_row = row + 1;
+    put_gutter(buffer, underline_row, gutter_width, None);
+    let mut labeled = vec![];
+    let secondary = annotations.iter().filter(|a| !a.is_primary);
+    let primary = annotations.iter().filter(|a| a.is_primary);
+    for annotation in secondary.chain(primary) {
+        let start_col = display_column(source_string, annotation.start_col);
+        let end_col = display_column(source_string, annotation.end_col);
+        let end_col = if end_col > start_col { end_col } else { start_col + 1 };
+        let style = underline_style(annotation.is_primary);
+
+        for col in start_col..end_col {
+            buffer.putc(underline_row, offset + col, '─', style);
+        }
+        if annotation.label.is_some() {
+            let anchor = start_col + (end_col - start_col) / 2;
+            buffer.putc(underline_row, offset + anchor, '┬', style);
+            labeled.push((anchor, annotation));
+        }
+    }
+
+    // Each label gets a row of its own, rightmost first, with all of the labels lined up
+    // after the rightmost arrow. The arrows of the labels still to come pass through the
+    // rows of the earlier ones.
+    labeled.sort_by_key(|&(anchor, _)| anchor);
+    let label_col = match labeled.last() {
+        Some(&(anchor, _)) => anchor + 4,
+        None => return,
+    };
+    for (i, &(anchor, annotation)) in labeled.iter().enumerate().rev() {
+        let label_row = buffer.num_lines();
+        put_gutter(buffer, label_row, gutter_width, None);
+
+        for &(other_anchor, other) in &labeled[..i] {
+            buffer.putc(label_row,
+                        offset + other_anchor,
+                        '│',
+                        underline_style(other.is_primary));
+        }
+        let style = underline_style(annotation.is_primary);
+        buffer.putc(label_row, offset + anchor, '╰', style);
+        for col in anchor + 1..label_col - 1 {
+            buffer.putc(label_row, offset + col, '─', style);
+        }
+        buffer.puts(label_row,
+                    offset + label_col,
+                    annotation.label.as_ref().unwrap(),
+                    label_style(annotation.is_primary));
+    }
+}
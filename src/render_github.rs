@@ -0,0 +1,84 @@
+use styled_buffer::Level;
+use compiler_message::*;
+use codemap::Span;
+
+/// Render the message as GitHub Actions workflow commands, so that CI shows it inline in
+/// the diff. The message itself goes at the primary location, and each secondary label
+/// becomes a `::warning` at its own location.
+///
+/// eg) ::error file=foo.rs,line=3,col=14::Unresolved name
+///
+/// Note that GitHub counts columns from 1.
+pub fn render_as_github_annotation(msg: &CompilerMessage) -> String {
+    let mut output = String::new();
+
+    let command = match msg.level {
+        Level::Bug | Level::Fatal | Level::PhaseFatal | Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Note | Level::Help => "notice",
+        Level::Cancelled => return output,
+    };
+    msg.mark_emitted();
+
+    // The override counts columns from 0, like the `-->` line, but GitHub counts from 1
+    let location = match msg.location_override {
+        Some((ref file, line, col)) => Some((file.clone(), line, col + 1)),
+        None => github_location(msg, msg.primary_span),
+    };
+    let message = match msg.error_code {
+        Some(ref code) => format!("{} [{}]", msg.primary_msg, code),
+        None => msg.primary_msg.clone(),
+    };
+    push_command(&mut output, command, location, &message);
+
+    for span_label in msg.span_labels.iter().filter(|x| !x.is_primary) {
+        let message = match span_label.label {
+            Some(ref label) => label,
+            None => &msg.primary_msg,
+        };
+        push_command(&mut output,
+                     "warning",
+                     github_location(msg, span_label.span),
+                     message);
+    }
+
+    output
+}
+
+fn push_command(output: &mut String,
+                command: &str,
+                location: Option<(String, usize, usize)>,
+                message: &str) {
+    output.push_str("::");
+    output.push_str(command);
+    if let Some((file, line, col)) = location {
+        output.push_str(&format!(" file={},line={},col={}",
+                                 escape_property(&file),
+                                 line,
+                                 col));
+    }
+    output.push_str("::");
+    output.push_str(&escape_data(message));
+    output.push('\n');
+}
+
+/// The file, line and 1-based column that a span starts at
+fn github_location(msg: &CompilerMessage, span: Span) -> Option<(String, usize, usize)> {
+    match msg.file_and_line_for_span(span) {
+        Some((file, line)) => {
+            let loc = msg.cm.lookup_char_pos(span.lo);
+            Some((file, line, loc.col.0 + 1))
+        }
+        None => None,
+    }
+}
+
+/// Escape the message of a workflow command so that it stays on one line
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+/// Escape a property of a workflow command, which also can't contain `:` or `,`
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
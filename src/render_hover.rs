@@ -0,0 +1,70 @@
+use compiler_message::*;
+use render_succinct::*;
+use styled_buffer::Level;
+
+/// Render the message as Markdown for an editor hover tooltip: the header in bold, the
+/// annotated source lines in a fenced code block, then a list of the labels with the
+/// text they point at in bold. Cancelled messages render as an empty string.
+pub fn render_for_editor_hover(msg: &CompilerMessage) -> String {
+    let mut output = String::new();
+    if msg.level == Level::Cancelled {
+        return output;
+    }
+    msg.mark_emitted();
+
+    // Header
+    // eg) **error: type mismatch [E123] (foo.rs:3:4)**
+    output.push_str("**");
+    output.push_str(&msg.level.to_string());
+    output.push_str(": ");
+    output.push_str(&msg.primary_msg);
+    if let Some(ref suffix) = msg.title_suffix {
+        output.push_str(&format!(" {}", suffix));
+    }
+    if let Some(ref code) = msg.error_code {
+        output.push_str(&format!(" [{}]", code));
+    }
+    if let Some(location) = hover_location(msg) {
+        output.push_str(&format!(" ({})", location));
+    }
+    output.push_str("**\n");
+
+    // The source snippet, without any underlines
+    let mut source_lines = line_annotations(msg).peekable();
+    if source_lines.peek().is_some() {
+        output.push_str("\n```\n");
+        for (_, source_string, _) in source_lines {
+            output.push_str(&source_string);
+            output.push('\n');
+        }
+        output.push_str("```\n");
+    }
+
+    // The labels, in the order they were added
+    let labels: Vec<_> = msg.span_labels.iter().filter(|x| x.label.is_some()).collect();
+    if !labels.is_empty() {
+        output.push('\n');
+        for span_label in labels {
+            let snippet = msg.cm.span_to_snippet(span_label.span).unwrap_or(String::new());
+            output.push_str(&format!("- **{}**: {}\n",
+                                     snippet,
+                                     span_label.label.as_ref().unwrap()));
+        }
+    }
+
+    output
+}
+
+/// The `file:line:col` of the message, counting columns from 0 like the `-->` line, or
+/// None if the primary span doesn't point at a line in the codemap
+fn hover_location(msg: &CompilerMessage) -> Option<String> {
+    if let Some((ref file, line, col)) = msg.location_override {
+        return Some(format!("{}:{}:{}", file, line, col));
+    }
+
+    if msg.file_and_line_for_span(msg.primary_span).is_none() {
+        return None;
+    }
+    let loc = msg.cm.lookup_char_pos(msg.primary_span.lo);
+    Some(format!("{}:{}:{}", loc.file.name, loc.line, loc.col.0))
+}
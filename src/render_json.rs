@@ -0,0 +1,156 @@
+use std::io::{self, Write};
+
+use compiler_message::*;
+use codemap::Span;
+use renderer::{Renderer, JsonRenderer};
+
+/// Render the message as a single-line JSON object terminated by a newline, so that a
+/// stream of messages can be fed to log aggregators expecting newline-delimited JSON.
+///
+/// eg) {"level":"error","message":"...","file":"foo.rs","line":3,"col":4,"labels":[],
+///      "suggestions":[],"code":null}
+pub fn render_compact_json(msg: &CompilerMessage) -> String {
+    msg.mark_emitted();
+    let mut output = render_json_header(msg);
+    output.push_str(&render_json_labels(msg));
+    output.push_str(&render_json_footer(msg));
+    output
+}
+
+impl Renderer for JsonRenderer {
+    fn render_header(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()> {
+        buf.write_all(render_json_header(msg).as_bytes())
+    }
+
+    fn render_source_block(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()> {
+        buf.write_all(render_json_labels(msg).as_bytes())
+    }
+
+    fn render_footer(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()> {
+        buf.write_all(render_json_footer(msg).as_bytes())
+    }
+}
+
+/// The start of the JSON object, up to and including the location of the message
+fn render_json_header(msg: &CompilerMessage) -> String {
+    let mut output = String::new();
+
+    output.push('{');
+    output.push_str(&format!("\"level\":{}", json_string(&msg.level.to_string())));
+    output.push_str(&format!(",\"message\":{}", json_string(&msg.primary_msg)));
+
+    let location = match msg.location_override {
+        Some((ref file, line, col)) => Some((file.clone(), line, col)),
+        None => location_for_span(msg, msg.primary_span),
+    };
+    match location {
+        Some((file, line, col)) => {
+            output.push_str(&format!(",\"file\":{},\"line\":{},\"col\":{}",
+                                     json_string(&file),
+                                     line,
+                                     col));
+        }
+        None => output.push_str(",\"file\":null,\"line\":null,\"col\":null"),
+    }
+
+    output
+}
+
+/// The `labels` field of the JSON object
+fn render_json_labels(msg: &CompilerMessage) -> String {
+    let mut output = String::new();
+
+    output.push_str(",\"labels\":[");
+    for (i, span_label) in msg.span_labels.iter().enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        output.push('{');
+        match location_for_span(msg, span_label.span) {
+            Some((_, line, col)) => output.push_str(&format!("\"line\":{},\"col\":{}", line, col)),
+            None => output.push_str("\"line\":null,\"col\":null"),
+        }
+        output.push_str(&format!(",\"primary\":{}", span_label.is_primary));
+        match span_label.label {
+            Some(ref label) => output.push_str(&format!(",\"label\":{}", json_string(label))),
+            None => output.push_str(",\"label\":null"),
+        }
+        output.push('}');
+    }
+    output.push(']');
+
+    output
+}
+
+/// The end of the JSON object: the suggestions, the error code and the trailing newline
+fn render_json_footer(msg: &CompilerMessage) -> String {
+    let mut output = String::new();
+
+    output.push_str(",\"suggestions\":[");
+    for (i, suggestion) in msg.suggestions.iter().enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        output.push('{');
+        match location_for_span(msg, suggestion.span) {
+            Some((_, line, col)) => output.push_str(&format!("\"line\":{},\"col\":{}", line, col)),
+            None => output.push_str("\"line\":null,\"col\":null"),
+        }
+        let replacement = msg.formatted_replacement(suggestion);
+        output.push_str(&format!(",\"replacement\":{}", json_string(&replacement)));
+        output.push_str(&format!(",\"machine_applicable\":{}", suggestion.machine_applicable));
+        output.push('}');
+    }
+    output.push(']');
+
+    match msg.error_code {
+        Some(ref code) => output.push_str(&format!(",\"code\":{}", json_string(code))),
+        None => output.push_str(",\"code\":null"),
+    }
+    output.push_str("}\n");
+
+    output
+}
+
+/// The file, line and column a span starts at, using the same column numbering as the
+/// `-->` line of the human-readable output
+fn location_for_span(msg: &CompilerMessage, span: Span) -> Option<(String, usize, usize)> {
+    match msg.file_and_line_for_span(span) {
+        Some((file, line)) => {
+            let loc = msg.cm.lookup_char_pos(span.lo);
+            Some((file, line, loc.col.0))
+        }
+        None => None,
+    }
+}
+
+/// Quote and escape a string as a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut output = String::with_capacity(s.len() + 2);
+    output.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::json_string;
+
+    #[test]
+    fn json_string_escapes() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a \"b\" \\ c"), "\"a \\\"b\\\" \\\\ c\"");
+        assert_eq!(json_string("line\nbreak\u{1}"), "\"line\\nbreak\\u0001\"");
+    }
+}
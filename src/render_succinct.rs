@@ -1,8 +1,18 @@
+use std::cmp;
+use std::env;
+use std::mem;
+use std::ops::Range;
 use std::rc::Rc;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use unicode_bidi::BidiInfo;
+use unicode_width::UnicodeWidthChar;
 
 use styled_buffer::*;
 use compiler_message::*;
-use codemap::{self, Span, CharPos, FileMap};
+use codemap::{Span, BytePos, CharPos, CodeMap, FileMap, Loc, SpanLabel, MacroBacktrace,
+              DUMMY_SP, NO_EXPANSION};
 
 struct FileWithAnnotatedLines {
     file: Rc<FileMap>,
@@ -17,40 +27,318 @@ struct Line {
 }
 
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq)]
-struct Annotation {
+pub struct Annotation {
     /// Start column, 0-based indexing -- counting *characters*, not
     /// utf-8 bytes. Note that it is important that this field goes
     /// first, so that when we sort, we sort orderings by start
     /// column.
-    start_col: usize,
+    pub start_col: usize,
 
     /// End column within the line (exclusive)
-    end_col: usize,
+    pub end_col: usize,
 
     /// Is this annotation derived from primary span
-    is_primary: bool,
+    pub is_primary: bool,
 
     /// Is this a large span minimized down to a smaller span
-    is_minimized: bool,
+    pub is_minimized: bool,
 
     /// Optional label to display adjacent to the annotation.
-    label: Option<String>,
+    pub label: Option<String>,
+}
+
+impl Annotation {
+    pub fn new(start_col: usize,
+               end_col: usize,
+               is_primary: bool,
+               is_minimized: bool)
+               -> Annotation {
+        Annotation {
+            start_col: start_col,
+            end_col: end_col,
+            is_primary: is_primary,
+            is_minimized: is_minimized,
+            label: None,
+        }
+    }
+
+    /// The same annotation, with `label` next to it
+    pub fn with_label(mut self, label: String) -> Annotation {
+        self.label = Some(label);
+        self
+    }
+
+    /// Does this annotation underline the given column
+    pub fn covers(&self, col: usize) -> bool {
+        self.start_col <= col && col < self.end_col
+    }
+
+    /// Split the annotation into one covering `start_col..col` and one covering
+    /// `col..end_col`. The label stays with the first half, as that's where it hangs from.
+    /// Returns None unless `col` is strictly inside the annotation, so that neither half
+    /// is empty.
+    pub fn split_at(&self, col: usize) -> Option<(Annotation, Annotation)> {
+        if col <= self.start_col || col >= self.end_col {
+            return None;
+        }
+
+        let first = Annotation {
+            start_col: self.start_col,
+            end_col: col,
+            is_primary: self.is_primary,
+            is_minimized: self.is_minimized,
+            label: self.label.clone(),
+        };
+        let second = Annotation {
+            start_col: col,
+            end_col: self.end_col,
+            is_primary: self.is_primary,
+            is_minimized: self.is_minimized,
+            label: None,
+        };
+        Some((first, second))
+    }
+
+    /// Combine two annotations that overlap or touch into one covering both of them, with
+    /// the labels joined left to right. Returns None if there's a gap between them, or if
+    /// only one of them is primary.
+    pub fn merge(a: &Annotation, b: &Annotation) -> Option<Annotation> {
+        if a.is_primary != b.is_primary || a.end_col < b.start_col || b.end_col < a.start_col {
+            return None;
+        }
+
+        let (left, right) = if b.start_col < a.start_col { (b, a) } else { (a, b) };
+        let label = match (&left.label, &right.label) {
+            (&Some(ref l), &Some(ref r)) => Some(format!("{}, {}", l, r)),
+            (&Some(ref l), &None) => Some(l.clone()),
+            (&None, &Some(ref r)) => Some(r.clone()),
+            (&None, &None) => None,
+        };
+
+        Some(Annotation {
+            start_col: cmp::min(a.start_col, b.start_col),
+            end_col: cmp::max(a.end_col, b.end_col),
+            is_primary: a.is_primary,
+            is_minimized: a.is_minimized || b.is_minimized,
+            label: label,
+        })
+    }
+}
+
+impl SpanLabel {
+    /// The annotation this label is drawn as, relative to the start of the line it starts
+    /// on. Returns None if the span isn't in `cm`.
+    pub fn as_annotation(&self, cm: &CodeMap) -> Option<Annotation> {
+        {
+            let files = cm.files.borrow();
+            match files.last() {
+                Some(last) if self.span.lo <= last.end_pos && self.span.hi <= last.end_pos => {}
+                _ => return None,
+            }
+        }
+        let (_, _, annotation) = locate_span_label(cm, &mut None, self);
+        Some(annotation)
+    }
+}
+
+/// Set by `set_old_school`, and whether it has been called at all
+static OLD_SCHOOL: AtomicBool = AtomicBool::new(false);
+static OLD_SCHOOL_SET: AtomicBool = AtomicBool::new(false);
+
+/// Whether `ERROR_REPORTER_OLD_SCHOOL` turns the format on, which is only looked up once
+/// per process
+static OLD_SCHOOL_ENV: AtomicBool = AtomicBool::new(false);
+static OLD_SCHOOL_ENV_INIT: Once = Once::new();
+
+/// Switch every message rendered by this process to the old-school `^~~~` format, or back.
+/// Until this is called, the format can be turned on by setting `ERROR_REPORTER_OLD_SCHOOL`
+/// to `1` or `true` in the environment.
+pub fn set_old_school(enable: bool) {
+    OLD_SCHOOL.store(enable, Ordering::SeqCst);
+    OLD_SCHOOL_SET.store(true, Ordering::SeqCst);
 }
 
 fn check_old_school() -> bool {
-    false
+    if OLD_SCHOOL_SET.load(Ordering::SeqCst) {
+        return OLD_SCHOOL.load(Ordering::SeqCst);
+    }
+
+    OLD_SCHOOL_ENV_INIT.call_once(|| {
+        let value = env::var("ERROR_REPORTER_OLD_SCHOOL").ok();
+        OLD_SCHOOL_ENV.store(old_school_env_enables(value.as_ref().map(|x| &x[..])),
+                             Ordering::SeqCst);
+    });
+    OLD_SCHOOL_ENV.load(Ordering::SeqCst)
+}
+
+/// Whether the value of `ERROR_REPORTER_OLD_SCHOOL` turns the format on
+fn old_school_env_enables(value: Option<&str>) -> bool {
+    match value {
+        Some(value) => value == "1" || value.eq_ignore_ascii_case("true"),
+        None => false,
+    }
 }
 
 pub fn render_succinct(msg: &CompilerMessage) -> Vec<Vec<StyledString>> {
-    // Create our styled buffer that we'll use to render the whole error message
+    render(msg, None)
+}
+
+/// Like `render_succinct`, but leaves out all of the secondary labels. The labels are
+/// put back once the message has been rendered.
+pub fn render_only_primary_span(msg: &mut CompilerMessage) -> Vec<Vec<StyledString>> {
+    let all_labels = mem::replace(&mut msg.span_labels, vec![]);
+    msg.span_labels = all_labels.iter().filter(|x| x.is_primary).cloned().collect();
+
+    let output = render_succinct(msg);
+
+    msg.span_labels = all_labels;
+    output
+}
+
+/// Like `render_succinct`, but only shows the source of the primary file and the first
+/// `max_files - 1` others, with a note saying how many more files there were. The note is
+/// taken back off once the message has been rendered.
+pub fn render_with_elided_files(msg: &mut CompilerMessage,
+                                max_files: usize)
+                                -> Vec<Vec<StyledString>> {
+    let max_files = cmp::max(max_files, 1);
+    let mut annotated_files = preprocess_annotations(msg);
+    if annotated_files.len() <= max_files {
+        return render(msg, None);
+    }
+
+    // The primary file is always first
+    let hidden = annotated_files.len() - max_files;
+    annotated_files.truncate(max_files);
+
+    msg.notes.push(format!("{} more {} not shown",
+                           hidden,
+                           if hidden == 1 { "file" } else { "files" }));
+    let output = render_files(msg, annotated_files, None);
+    msg.notes.pop();
+    output
+}
+
+/// Like `render_succinct`, but wraps labels at word boundaries so that they fit within
+/// `terminal_width` columns
+pub fn render_with_width(msg: &CompilerMessage, terminal_width: usize) -> Vec<Vec<StyledString>> {
+    render(msg, Some(terminal_width))
+}
+
+fn render(msg: &CompilerMessage, terminal_width: Option<usize>) -> Vec<Vec<StyledString>> {
+    render_files(msg, preprocess_annotations(msg), terminal_width)
+}
+
+fn render_files(msg: &CompilerMessage,
+                annotated_files: Vec<FileWithAnnotatedLines>,
+                terminal_width: Option<usize>)
+                -> Vec<Vec<StyledString>> {
+    msg.mark_emitted();
+
+    // Create our styled buffer that we'll use to render the whole error message. Each label
+    // usually takes a source line, an underline and a couple of rows for its text.
+    let mut buffer = StyledBuffer::with_capacity(3 + 4 * msg.span_labels.len(), 100);
+
+    render_header(msg, &mut buffer);
+    render_annotated_files(msg, &mut buffer, annotated_files, terminal_width);
+    render_footer(msg, &mut buffer);
+
+    // final step: take our styled buffer and render it, followed by any related messages
+    let mut output = padding_rows(msg.padding);
+    output.extend(buffer.render());
+    output.extend(render_related(msg, terminal_width));
+    output.extend(padding_rows(msg.padding));
+    output
+}
+
+/// The blank rows put before and after a message by `with_padding`
+pub fn padding_rows(count: usize) -> Vec<Vec<StyledString>> {
+    (0..count)
+        .map(|_| {
+            vec![StyledString {
+                     text: String::new(),
+                     style: Style::Padding,
+                 }]
+        })
+        .collect()
+}
+
+/// How far related messages are indented under the message they belong to
+const RELATED_INDENT: &'static str = "    ";
+
+/// Render the messages related to this one, each indented under the main message
+pub fn render_related(msg: &CompilerMessage,
+                      terminal_width: Option<usize>)
+                      -> Vec<Vec<StyledString>> {
+    let mut output = vec![];
+
+    for related in &msg.related {
+        if related.level == Level::Cancelled || related.suppressed {
+            continue;
+        }
+
+        // Keep the related message inside the terminal once it's been indented
+        let terminal_width = terminal_width.map(|w| w.saturating_sub(RELATED_INDENT.len()));
+        for mut line in render(related, terminal_width) {
+            line.insert(0,
+                        StyledString {
+                            text: String::from(RELATED_INDENT),
+                            style: Style::NoStyle,
+                        });
+            output.push(line);
+        }
+    }
+
+    output
+}
+
+/// Render a message about `source` without setting up a codemap first, eg) for fuzz tests
+/// and documentation examples. Labels are given as byte ranges into `source`, and the
+/// first label is the primary one.
+pub fn render_with_codemap_source(source: &str,
+                                  level: Level,
+                                  message: &str,
+                                  labels: &[(Range<usize>, Option<&str>)])
+                                  -> Vec<Vec<StyledString>> {
+    let ranges: Vec<_> = labels.iter().map(|&(ref range, _)| range.clone()).collect();
+    let (cm, spans) = anon_source_spans(source, &ranges);
+
+    let primary_span = spans.first().cloned().unwrap_or(DUMMY_SP);
+    let mut msg = CompilerMessage::new(level, String::from(message), primary_span, None, cm);
+    for (&span, &(_, label)) in spans.iter().zip(labels) {
+        msg.span_label(span, label.map(String::from));
+    }
+
+    render_succinct(&msg)
+}
+
+/// Render only the annotated source of the message, without the header or any notes,
+/// eg) for embedding a snippet in documentation
+pub fn render_source_block(msg: &CompilerMessage) -> Vec<Vec<StyledString>> {
+    msg.mark_emitted();
     let mut buffer = StyledBuffer::new();
 
+    render_source_lines(msg, &mut buffer);
+
+    buffer.render()
+}
+
+/// Render the header line of the message
+pub fn render_header(msg: &CompilerMessage, buffer: &mut StyledBuffer) {
+    if msg.suppress_header {
+        return;
+    }
+
     // Header line
     // eg) error: type mismatch [E123]
     // TODO: still needs error number
     buffer.append(0, &msg.level.to_string(), Style::Level(msg.level));
     buffer.append(0, ": ", Style::HeaderMsg);
     buffer.append(0, &msg.primary_msg.clone(), Style::HeaderMsg);
+    if let Some(ref suffix) = msg.title_suffix {
+        buffer.append(0, " ", Style::HeaderMsg);
+        buffer.append(0, suffix, Style::HeaderMsg);
+    }
     match msg.error_code {
         Some(ref code) => {
             buffer.append(0, " [", Style::ErrorCode);
@@ -59,56 +347,82 @@ pub fn render_succinct(msg: &CompilerMessage) -> Vec<Vec<StyledString>> {
         }
         _ => {}
     }
+}
 
+/// Render the annotated source lines of the message, grouped by file with the primary
+/// file first
+pub fn render_source_lines(msg: &CompilerMessage, buffer: &mut StyledBuffer) {
     // Preprocess all the annotations so that they are grouped by file and by line number
     // This helps us quickly iterate over the whole message (including secondary file spans)
-    let mut annotated_files = preprocess_annotations(msg);
+    let annotated_files = preprocess_annotations(msg);
+
+    render_annotated_files(msg, buffer, annotated_files, None);
+}
+
+/// Render the annotated source lines of the message in the order their spans appear in
+/// the codemap. Unlike `render_source_lines`, the primary file is not moved to the front.
+pub fn render_source_lines_sorted_by_span(msg: &CompilerMessage, buffer: &mut StyledBuffer) {
+    let annotated_files = preprocess_annotations_by_span(msg);
+
+    render_annotated_files(msg, buffer, annotated_files, None);
+}
+
+fn render_annotated_files(msg: &CompilerMessage,
+                          buffer: &mut StyledBuffer,
+                          annotated_files: Vec<FileWithAnnotatedLines>,
+                          terminal_width: Option<usize>) {
+    if msg.suppress_source {
+        return;
+    }
 
     // figure out the largest line number so we can align the line number column
     let max_line_num = get_max_line_num(msg);
     let len_of_max_line_num = max_line_num.to_string().len();
+    let old_school = check_old_school();
 
-    // Make sure our primary file comes first
-    let primary_lo = msg.cm.lookup_char_pos(msg.primary_span.lo);
-    if let Ok(pos) =
-            annotated_files.binary_search_by(|x| x.file.name.cmp(&primary_lo.file.name)) {
-        annotated_files.swap(0, pos);
+    if annotated_files.is_empty() {
+        // With an explicit location and no source to annotate, all we can show is where
+        if msg.location_override.is_some() {
+            render_primary_location(msg, buffer, len_of_max_line_num);
+        }
+        return;
     }
 
+    let primary_lo = msg.cm.lookup_char_pos(msg.primary_span.lo);
+    let mut seen_primary_file = false;
+
     // Print out the annotate source lines that correspond with the error
-    for annotated_file in annotated_files {
+    for (file_idx, annotated_file) in annotated_files.into_iter().enumerate() {
+
+        // Every file after the first is separated from the one before by a spacing line,
+        // whether or not it's the primary file
+        if file_idx > 0 {
+            let buffer_msg_line_offset = buffer.num_lines();
+            buffer.puts(buffer_msg_line_offset,
+                        len_of_max_line_num + 1,
+                        "|>",
+                        Style::LineNumber);
+        }
 
-        // print out the span location and spacer before we print the annotated source
+        // print out the span location before we print the annotated source
         // to do this, we need to know if this span will be primary
-        let is_primary = primary_lo.file.name == annotated_file.file.name;
+        let is_primary = !seen_primary_file &&
+                         primary_lo.file.name == annotated_file.file.name;
         if is_primary {
-            // remember where we are in the output buffer for easy reference
-            let mut buffer_msg_line_offset = buffer.num_lines();
+            seen_primary_file = true;
 
-            buffer.prepend(buffer_msg_line_offset, "--> ", Style::LineNumber);
-            let loc = msg.cm.lookup_char_pos(msg.primary_span.lo);
-            buffer.append(buffer_msg_line_offset,
-                            &format!("{}:{}:{}", loc.file.name, loc.line, loc.col.0),
-                            Style::LineAndColumn);
-            for i in 0..len_of_max_line_num {
-                buffer.prepend(buffer_msg_line_offset, " ", Style::NoStyle);
-            }
+            render_primary_location(msg, buffer, len_of_max_line_num);
         } else {
             // remember where we are in the output buffer for easy reference
-            let mut buffer_msg_line_offset = buffer.num_lines();
+            let buffer_msg_line_offset = buffer.num_lines();
 
-            // Add spacing line
-            buffer.puts(buffer_msg_line_offset,
-                        len_of_max_line_num + 1,
-                        "|>",
-                        Style::LineNumber);
-            // Then, the secondary file indicator
-            buffer.prepend(buffer_msg_line_offset + 1, "::: ", Style::LineNumber);
-            buffer.append(buffer_msg_line_offset + 1,
+            // The secondary file indicator
+            buffer.prepend(buffer_msg_line_offset, "::: ", Style::LineNumber);
+            buffer.append(buffer_msg_line_offset,
                             &annotated_file.file.name,
                             Style::LineAndColumn);
-            for i in 0..len_of_max_line_num {
-                buffer.prepend(buffer_msg_line_offset + 1, " ", Style::NoStyle);
+            for _ in 0..len_of_max_line_num {
+                buffer.prepend(buffer_msg_line_offset, " ", Style::NoStyle);
             }
         }
 
@@ -121,10 +435,12 @@ pub fn render_succinct(msg: &CompilerMessage) -> Vec<Vec<StyledString>> {
 
         // Next, output the annotate source for this file
         for line_idx in 0..annotated_file.lines.len() {
-            render_source_line(msg, &mut buffer,
+            render_source_line(msg, buffer,
                                     annotated_file.file.clone(),
                                     &annotated_file.lines[line_idx],
-                                    3 + len_of_max_line_num);
+                                    3 + len_of_max_line_num,
+                                    terminal_width,
+                                    old_school);
 
             // check to see if we need to print out or elide lines that come between
             // this annotated line and the next one
@@ -135,9 +451,10 @@ pub fn render_succinct(msg: &CompilerMessage) -> Vec<Vec<StyledString>> {
                     let last_buffer_line_num = buffer.num_lines();
                     buffer.puts(last_buffer_line_num, 0, "...", Style::LineNumber);
                 } else if line_idx_delta == 2 {
-                    let unannotated_line = annotated_file.file
-                        .get_line(annotated_file.lines[line_idx].line_number)
-                        .unwrap_or("");
+                    let unannotated_line = get_source_line(msg,
+                                                           &annotated_file.file,
+                                                           annotated_file.lines[line_idx]
+                                                               .line_number);
 
                     let last_buffer_line_num = buffer.num_lines();
 
@@ -152,15 +469,63 @@ pub fn render_succinct(msg: &CompilerMessage) -> Vec<Vec<StyledString>> {
                                 Style::LineNumber);
                     buffer.puts(last_buffer_line_num,
                                 3 + len_of_max_line_num,
-                                &unannotated_line,
+                                &unannotated_source_line(msg, unannotated_line),
                                 Style::Quotation);
                 }
             }
         }
     }
+}
+
+/// A line of source shown without annotations, eg) between two annotated lines. It is cut
+/// down to the visible columns and has its tabs expanded, the same as an annotated line.
+fn unannotated_source_line(msg: &CompilerMessage, source: &str) -> String {
+    match msg.visible_column_range {
+        Some(ref range) => expand_tabs(&visible_column_range(source, range).0).0,
+        None => expand_tabs(source).0,
+    }
+}
+
+fn render_primary_location(msg: &CompilerMessage,
+                           buffer: &mut StyledBuffer,
+                           len_of_max_line_num: usize) {
+    // remember where we are in the output buffer for easy reference
+    let buffer_msg_line_offset = buffer.num_lines();
+
+    let location = match msg.location_override {
+        Some((ref file, line, col)) => format!("{}:{}:{}", file, line, col),
+        None => {
+            let loc = msg.cm.lookup_char_pos(msg.primary_span.lo);
+            if loc.line == 0 {
+                // Files without lines (eg, empty files) have nowhere in them to point at
+                loc.file.name.clone()
+            } else {
+                format!("{}:{}:{}", loc.file.name, loc.line, loc.col.0)
+            }
+        }
+    };
+
+    buffer.prepend(buffer_msg_line_offset, "--> ", Style::LineNumber);
+    buffer.append(buffer_msg_line_offset, &location, Style::LineAndColumn);
+    for _ in 0..len_of_max_line_num {
+        buffer.prepend(buffer_msg_line_offset, " ", Style::NoStyle);
+    }
+}
+
+/// Render the notes, macro backtrace, suggestions and backtrace that follow the source
+pub fn render_footer(msg: &CompilerMessage, buffer: &mut StyledBuffer) {
+    let max_line_num = get_max_line_num(msg);
+    let len_of_max_line_num = max_line_num.to_string().len();
+
+    // Like the standard library, only show backtraces when RUST_BACKTRACE asks for them
+    let show_backtrace = msg.backtrace.is_some() &&
+                         env::var("RUST_BACKTRACE").map(|x| x != "0").unwrap_or(false);
+
+    let macro_backtrace = primary_macro_backtrace(msg);
 
-    // write out the notes that don't have a span
-    if !msg.notes.is_empty() {
+    // write out the notes and suggestions that don't have a span
+    if !msg.notes.is_empty() || !msg.suggestions.is_empty() || !macro_backtrace.is_empty() ||
+       show_backtrace {
         // Put in the spacer in before the notes
         let mut buffer_msg_line_offset = buffer.num_lines();
         buffer.puts(buffer_msg_line_offset,
@@ -175,14 +540,72 @@ pub fn render_succinct(msg: &CompilerMessage) -> Vec<Vec<StyledString>> {
         buffer.append(last_buffer_line_num, "note: ", Style::Level(Level::Note));
         buffer.append(last_buffer_line_num, &note, Style::NoStyle);
     }
+    for trace in &macro_backtrace {
+        let last_buffer_line_num = buffer.num_lines();
 
-    // final step: take our styled buffer and render it
-    buffer.render()
+        buffer.puts(last_buffer_line_num, 1 + len_of_max_line_num, "=> ", Style::LineNumber);
+        buffer.append(last_buffer_line_num, "note: ", Style::Level(Level::Note));
+        buffer.append(last_buffer_line_num,
+                      &format!("in expansion of {}", trace.macro_decl_name),
+                      Style::NoStyle);
+    }
+    for suggestion in &msg.suggestions {
+        let last_buffer_line_num = buffer.num_lines();
+
+        buffer.puts(last_buffer_line_num, 1 + len_of_max_line_num, "=> ", Style::LineNumber);
+        buffer.append(last_buffer_line_num, "help: ", Style::Level(Level::Help));
+        buffer.append(last_buffer_line_num,
+                      &format!("replace with `{}`", msg.formatted_replacement(suggestion)),
+                      Style::NoStyle);
+    }
+    if show_backtrace {
+        let last_buffer_line_num = buffer.num_lines();
+
+        buffer.puts(last_buffer_line_num, 1 + len_of_max_line_num, "=> ", Style::LineNumber);
+        buffer.append(last_buffer_line_num, "note: ", Style::Level(Level::Note));
+        buffer.append(last_buffer_line_num, "backtrace:", Style::NoStyle);
+
+        let backtrace = msg.backtrace.as_ref().unwrap().to_string();
+        for line in backtrace.lines() {
+            let last_buffer_line_num = buffer.num_lines();
+            buffer.puts(last_buffer_line_num, 0, line, Style::Level(Level::Note));
+        }
+    }
+}
+
+/// Render the message as plain text, one `String` per output line, dropping the styles
+pub fn render_to_vec_of_strings(msg: &CompilerMessage) -> Vec<String> {
+    render_succinct(msg)
+        .iter()
+        .map(|line| line.iter().map(|s| &s.text[..]).collect())
+        .collect()
+}
+
+impl From<CompilerMessage> for Vec<String> {
+    fn from(msg: CompilerMessage) -> Vec<String> {
+        render_to_vec_of_strings(&msg)
+    }
+}
+
+/// Render the message as plain text, with each line ending in a newline, dropping the styles
+pub fn render_to_string_lossy(msg: &CompilerMessage) -> String {
+    let mut output = String::new();
+    for line in render_to_vec_of_strings(msg) {
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output
+}
+
+impl From<CompilerMessage> for String {
+    fn from(msg: CompilerMessage) -> String {
+        render_to_string_lossy(&msg)
+    }
 }
 
 fn get_max_line_num(msg: &CompilerMessage) -> usize {
     let mut max = 0;
-    for span_label in &msg.span_labels {
+    for span_label in &all_span_labels(msg) {
         let hi = msg.cm.lookup_char_pos(span_label.span.hi);
         if hi.line > max {
             max = hi.line;
@@ -191,7 +614,40 @@ fn get_max_line_num(msg: &CompilerMessage) -> usize {
     max
 }
 
+/// If the primary span comes from a macro expansion, the chain of macro invocations that
+/// produced it
+fn primary_macro_backtrace(msg: &CompilerMessage) -> Vec<MacroBacktrace> {
+    if msg.cm.files.borrow().is_empty() {
+        return vec![];
+    }
+
+    if msg.cm.lookup_char_pos(msg.primary_span.lo).file.is_real_file() {
+        vec![]
+    } else {
+        msg.cm.macro_backtrace(msg.primary_span)
+    }
+}
+
+/// The span labels of the message, plus secondary labels pointing at the call sites of
+/// any macros the primary span was expanded from. Like `sort_labels_by_span`, these are
+/// sorted by span so that the order the labels were added in doesn't affect rendering.
+pub fn all_span_labels(msg: &CompilerMessage) -> Vec<SpanLabel> {
+    let mut span_labels = msg.span_labels.clone();
+    for trace in primary_macro_backtrace(msg) {
+        span_labels.push(SpanLabel::secondary(trace.call_site,
+                                              String::from("in this macro invocation")));
+    }
+    span_labels.sort_by_key(|x| (x.span.lo, !x.is_primary));
+    span_labels
+}
+
 fn preprocess_annotations(msg: &CompilerMessage) -> Vec<FileWithAnnotatedLines> {
+    // Nothing to annotate, so don't bother looking anything up in the codemap. Macro
+    // call sites are still shown for an expanded primary span, even without labels.
+    if msg.span_labels.is_empty() && msg.primary_span.expn_id == NO_EXPANSION {
+        return vec![];
+    }
+
     fn add_annotation_to_file(file_vec: &mut Vec<FileWithAnnotatedLines>,
                                 file: Rc<FileMap>,
                                 line_number: usize,
@@ -227,53 +683,289 @@ fn preprocess_annotations(msg: &CompilerMessage) -> Vec<FileWithAnnotatedLines>
     }
 
     let mut output = vec![];
+    let mut cache = None;
 
-    for span_label in &msg.span_labels {
-        let lo = msg.cm.lookup_char_pos(span_label.span.lo);
-        let hi = msg.cm.lookup_char_pos(span_label.span.hi);
+    for span_label in &all_span_labels(msg) {
+        let (file, line_number, ann) = span_label_to_annotation(msg, &mut cache, span_label);
+        add_annotation_to_file(&mut output, file, line_number, ann);
+    }
+
+    if output.is_empty() {
+        return output;
+    }
+
+    // Make sure our primary file comes first, followed by the rest of the files in
+    // alphabetical order so that the output is deterministic
+    let primary_lo = msg.cm.lookup_char_pos(msg.primary_span.lo);
+    output.sort_by_key(|x| (x.file.name != primary_lo.file.name, x.file.name.clone()));
+
+    output
+}
+
+fn preprocess_annotations_by_span(msg: &CompilerMessage) -> Vec<FileWithAnnotatedLines> {
+    if msg.span_labels.is_empty() && msg.primary_span.expn_id == NO_EXPANSION {
+        return vec![];
+    }
+
+    let mut span_labels = all_span_labels(msg);
+    span_labels.sort_by_key(|x| x.span.lo);
+
+    let mut output: Vec<FileWithAnnotatedLines> = vec![];
+    let mut cache = None;
+
+    for span_label in &span_labels {
+        let (file, line_number, ann) = span_label_to_annotation(msg, &mut cache, span_label);
+
+        // Files occupy contiguous ranges of the codemap, so once sorted we only have to
+        // check whether we're still in the same file as the previous label
+        if let Some(slot) = output.last_mut() {
+            if slot.file.name == file.name {
+                if let Some(line_slot) = slot.lines.last_mut() {
+                    if line_slot.line_number == line_number {
+                        line_slot.annotations.push(ann);
+                        continue;
+                    }
+                }
+                slot.lines.push(Line {
+                    line_number: line_number,
+                    annotations: vec![ann],
+                });
+                continue;
+            }
+        }
+        output.push(FileWithAnnotatedLines {
+            file: file,
+            lines: vec![Line {
+                            line_number: line_number,
+                            annotations: vec![ann],
+                        }],
+        });
+    }
+
+    output
+}
+
+fn span_label_to_annotation(msg: &CompilerMessage,
+                            cache: &mut LookupCache,
+                            span_label: &SpanLabel)
+                            -> (Rc<FileMap>, usize, Annotation) {
+    let (file, line, mut annotation) = locate_span_label(&msg.cm, cache, span_label);
+    annotation.label = annotation.label
+        .map(|label| msg.formatted_label(&label, span_label.is_primary));
+    (file, line, annotation)
+}
+
+/// The line found by the last codemap lookup made while preprocessing a message. Finding
+/// the line of a position means searching the file's lines, but labels usually start and
+/// end on the same line as the label before them.
+struct CachedLine {
+    file: Rc<FileMap>,
+
+    /// The (1-based) line number
+    line: usize,
+
+    /// The positions of the start of the line, and of the start of the next one (or one
+    /// past the end of the file for its last line)
+    lo: BytePos,
+    hi: BytePos,
+
+    /// The position of the start of the line, in characters
+    lo_char: CharPos,
+}
+
+type LookupCache = Option<CachedLine>;
 
-        // If the span is multi-line, simplify down to the span of one character
-        let (start_col, mut end_col, is_minimized) = if lo.line != hi.line {
-            (lo.col, CharPos(lo.col.0 + 1), true)
+/// The same as `cm.lookup_char_pos(pos)`, but only searches for the line when `pos` isn't
+/// on the line of the previous lookup
+fn cached_lookup(cm: &CodeMap, cache: &mut LookupCache, pos: BytePos) -> Loc {
+    if let Some(ref cached) = *cache {
+        if cached.lo <= pos && pos < cached.hi {
+            return Loc {
+                file: cached.file.clone(),
+                line: cached.line,
+                col: cm.bytepos_to_file_charpos(pos) - cached.lo_char,
+            };
+        }
+    }
+
+    let loc = cm.lookup_char_pos(pos);
+
+    // Files without line information give us a line of 0, and there's nothing to cache
+    if loc.line > 0 {
+        let lines = loc.file.lines.borrow();
+        let lo = lines[loc.line - 1];
+        let hi = if loc.line < lines.len() {
+            lines[loc.line]
         } else {
-            (lo.col, hi.col, false)
+            loc.file.end_pos + BytePos(1)
         };
+        *cache = Some(CachedLine {
+            file: loc.file.clone(),
+            line: loc.line,
+            lo: lo,
+            hi: hi,
+            lo_char: cm.bytepos_to_file_charpos(lo),
+        });
+    }
+    loc
+}
 
-        // Watch out for "empty spans". If we get a span like 6..6, we
-        // want to just display a `^` at 6, so convert that to
-        // 6..7. This is degenerate input, but it's best to degrade
-        // gracefully -- and the parser likes to supply a span like
-        // that for EOF, in particular.
-        if start_col == end_col {
-            end_col.0 += 1;
-        }
+/// The file and line a label starts on, and the annotation it is drawn as on that line
+fn locate_span_label(cm: &CodeMap,
+                     cache: &mut LookupCache,
+                     span_label: &SpanLabel)
+                     -> (Rc<FileMap>, usize, Annotation) {
+    let lo = cached_lookup(cm, cache, span_label.span.lo);
+    let hi = cached_lookup(cm, cache, span_label.span.hi);
 
-        add_annotation_to_file(&mut output,
-                                lo.file,
-                                lo.line,
-                                Annotation {
-                                    start_col: lo.col.0,
-                                    end_col: hi.col.0,
-                                    is_primary: span_label.is_primary,
-                                    is_minimized: is_minimized,
-                                    label: span_label.label.clone(),
-                                });
+    // If the span is multi-line, simplify down to the span of one character
+    let (start_col, mut end_col, is_minimized) = if lo.line != hi.line {
+        (lo.col, CharPos(lo.col.0 + 1), true)
+    } else {
+        (lo.col, hi.col, false)
+    };
+
+    // Watch out for "empty spans". If we get a span like 6..6, we
+    // want to just display a `^` at 6, so convert that to
+    // 6..7. This is degenerate input, but it's best to degrade
+    // gracefully -- and the parser likes to supply a span like
+    // that for EOF, in particular.
+    if start_col == end_col {
+        end_col.0 += 1;
+    }
+
+    (lo.file,
+     lo.line,
+     Annotation {
+         start_col: start_col.0,
+         end_col: end_col.0,
+         is_primary: span_label.is_primary,
+         is_minimized: is_minimized,
+         label: span_label.label.clone(),
+     })
+}
+
+/// Walk the annotated source lines of a message, in the order they would be rendered,
+/// without writing to a buffer. Each item is the line number, the source text of the
+/// line, and its annotations sorted by column.
+pub fn line_annotations<'a>(msg: &'a CompilerMessage)
+                            -> impl Iterator<Item = (usize, String, Vec<Annotation>)> + 'a {
+    file_annotations(msg).into_iter().flat_map(|(_, lines)| lines)
+}
+
+/// The labels of the message and the annotations they become, line by line, as they are
+/// before anything is drawn. For working out why a message renders the way it does.
+pub fn debug_repr(msg: &CompilerMessage) -> String {
+    let mut output = format!("{:?}\n", msg);
+
+    output.push_str("span labels:\n");
+    for span_label in &all_span_labels(msg) {
+        output.push_str(&format!("    {:?}\n", span_label));
+    }
+
+    for annotated_file in preprocess_annotations(msg) {
+        output.push_str(&format!("file {}:\n", annotated_file.file.name));
+        for line in &annotated_file.lines {
+            output.push_str(&format!("    line {}:\n", line.line_number));
+            for annotation in &line.annotations {
+                output.push_str(&format!("        {:?}\n", annotation));
+            }
+        }
     }
     output
 }
 
+/// The same as `line_annotations`, but grouped by the file the lines come from
+pub fn file_annotations(msg: &CompilerMessage)
+                        -> Vec<(Rc<FileMap>, Vec<(usize, String, Vec<Annotation>)>)> {
+    preprocess_annotations(msg)
+        .into_iter()
+        .map(|annotated_file| {
+            let file = annotated_file.file;
+            let lines = annotated_file.lines
+                .into_iter()
+                .map(|line| {
+                    let source_string = if line.line_number == 0 {
+                        String::new()
+                    } else {
+                        get_source_line(msg, &file, line.line_number - 1).to_string()
+                    };
+                    let mut annotations = line.annotations;
+                    annotations.sort();
+                    (line.line_number, source_string, annotations)
+                })
+                .collect();
+            (file, lines)
+        })
+        .collect()
+}
+
+/// Every annotation that would be rendered, as `(line_number, start_col, label, is_primary)`,
+/// for clients that draw their own underlines
+pub fn render_all_labels_flat(msg: &CompilerMessage) -> Vec<(usize, usize, Option<String>, bool)> {
+    line_annotations(msg)
+        .flat_map(|(line_number, _, annotations)| {
+            annotations.into_iter()
+                .map(move |a| (line_number, a.start_col, a.label, a.is_primary))
+        })
+        .collect()
+}
+
+/// Get a (0-based) line of source from the codemap, falling back to the message's
+/// in-memory files when the codemap doesn't have the text of the file
+pub fn get_source_line<'a>(msg: &'a CompilerMessage,
+                           file: &'a FileMap,
+                           line_index: usize)
+                           -> &'a str {
+    file.get_line(line_index)
+        .or_else(|| {
+            msg.in_memory_files
+                .get(&file.name)
+                .and_then(|lines| lines.get(line_index))
+                .map(|x| &x[..])
+        })
+        .unwrap_or("")
+}
+
 fn render_source_line(msg: &CompilerMessage,
                         buffer: &mut StyledBuffer,
                         file: Rc<FileMap>,
                         line: &Line,
-                        width_offset: usize) {
-    let source_string = file.get_line(line.line_number - 1)
-        .unwrap_or("");
-
+                        width_offset: usize,
+                        terminal_width: Option<usize>,
+                        old_school: bool) {
     let line_offset = buffer.num_lines();
 
-    // First create the source line we will highlight.
-    buffer.puts(line_offset, width_offset, &source_string, Style::Quotation);
+    // Spans into a file with no lines (eg, an empty file) don't have a line number.
+    // There's nothing to underline, so just say so and let the other lines render.
+    if line.line_number == 0 {
+        buffer.puts(line_offset, width_offset - 2, "|>", Style::LineNumber);
+        buffer.puts(line_offset, width_offset, "<empty file>", Style::OldSchoolNoteText);
+        return;
+    }
+
+    let mut source_string = get_source_line(msg, &file, line.line_number - 1);
+    let mut line_annotations = line.annotations.clone();
+    let visible_source;
+    if let Some(ref range) = msg.visible_column_range {
+        let (visible, map_column) = visible_column_range(source_string, range);
+        for annotation in &mut line_annotations {
+            let was_empty = annotation.start_col == annotation.end_col;
+            annotation.start_col = map_column(annotation.start_col);
+            annotation.end_col = map_column(annotation.end_col);
+            // Keep something to point at for spans that are entirely out of view
+            if !was_empty && annotation.end_col <= annotation.start_col {
+                annotation.end_col = annotation.start_col + 1;
+            }
+        }
+        visible_source = visible;
+        source_string = &visible_source;
+    }
+
+    // First create the source line we will highlight, with any tabs expanded to spaces
+    // so that the rows below can line up with it.
+    let (expanded_source, source_cells) = expand_tabs(&source_string);
+    buffer.puts(line_offset, width_offset, &expanded_source, Style::Quotation);
     buffer.puts(line_offset,
                 0,
                 &(line.line_number.to_string()),
@@ -281,7 +973,31 @@ fn render_source_line(msg: &CompilerMessage,
 
     buffer.puts(line_offset, width_offset - 2, "|>", Style::LineNumber);
 
-    if line.annotations.is_empty() {
+    // Malformed spans (eg, from macro expanders) can give us annotations that run backwards
+    // or past the end of the source line. Rather than drawing garbage, we skip the ones that
+    // run backwards and cut the others off at the end of the line. One column past the end
+    // is still allowed for annotations that start there, as that's where the newline is,
+    // eg) for an "expected `;`" at the end of the line.
+    let source_len = source_string.chars().count();
+    let mut annotations: Vec<Annotation> = line_annotations
+        .iter()
+        .filter(|a| a.start_col <= a.end_col)
+        .map(|a| {
+            let mut a = a.clone();
+            let is_at_newline = a.start_col == source_len && a.end_col == source_len + 1;
+            if a.end_col > source_len && !is_at_newline {
+                if a.start_col >= source_len {
+                    a.start_col = source_len;
+                    a.end_col = source_len + 1;
+                } else {
+                    a.end_col = source_len;
+                }
+            }
+            a
+        })
+        .collect();
+
+    if annotations.is_empty() {
         return;
     }
 
@@ -305,17 +1021,86 @@ fn render_source_line(msg: &CompilerMessage,
     // and "annotations lines", where the highlight lines have the `~`.
 
     // let mut highlight_line = Self::whitespace(&source_string);
-    let old_school = check_old_school();
 
     // Sort the annotations by (start, end col)
-    let mut annotations = line.annotations.clone();
     annotations.sort();
 
-    // Next, create the highlight line.
-    for annotation in &annotations {
+    // Work out which columns each annotation covers on screen. Usually that's just the
+    // run of columns from its start to its end, but once a terminal has reordered
+    // right-to-left text, the characters of a span can end up anywhere on the line.
+    let rtl_columns = if msg.rtl_support {
+        visual_columns(&expanded_source)
+    } else {
+        None
+    };
+    let expanded_chars: Vec<char> = expanded_source.chars().collect();
+    let underline_columns = |annotation: &Annotation| -> Vec<usize> {
+        match rtl_columns {
+            Some(ref visual_columns) => {
+                let mut columns = vec![];
+                for p in annotation.start_col..annotation.end_col {
+                    // The newline isn't part of the source, but it goes at the end of the line
+                    if p >= source_cells.len() {
+                        columns.push(display_column(&source_string, p));
+                        continue;
+                    }
+
+                    // A tab takes up several characters once expanded
+                    let first_cell = source_cells[p];
+                    let last_cell = source_cells.get(p + 1)
+                        .cloned()
+                        .unwrap_or(visual_columns.len());
+                    for cell in first_cell..last_cell {
+                        let width = expanded_chars[cell].width().unwrap_or(1);
+                        columns.extend(visual_columns[cell]..visual_columns[cell] + width);
+                    }
+                }
+                columns.sort();
+                columns
+            }
+            None => {
+                (display_column(&source_string, annotation.start_col)..
+                 display_column(&source_string, annotation.end_col))
+                    .collect()
+            }
+        }
+    };
+    // The column that labels hang from, and the column just past the end of the underline
+    let start_column = |annotation: &Annotation| -> usize {
+        underline_columns(annotation)
+            .first()
+            .cloned()
+            .unwrap_or(display_column(&source_string, annotation.start_col))
+    };
+    let end_column = |annotation: &Annotation| -> usize {
+        underline_columns(annotation)
+            .last()
+            .map(|x| x + 1)
+            .unwrap_or(display_column(&source_string, annotation.end_col))
+    };
+
+    // Next, create the highlight line. The source line is stored one character per
+    // column, but the rows below it are positioned by display width so that they still
+    // line up under wide characters.
+    //
+    // Secondary annotations are drawn first, so that where one shares columns with a
+    // primary annotation the primary `^` is the one left showing. The exception is an
+    // annotation that starts at the same column as a longer one: it would be hidden
+    // completely, so it's drawn again on top, innermost last.
+    let secondary_annotations = annotations.iter().filter(|a| !a.is_primary);
+    let primary_annotations = annotations.iter().filter(|a| a.is_primary);
+    let mut nested_annotations: Vec<&Annotation> = annotations.iter()
+        .filter(|a| {
+            annotations.iter().any(|b| b.start_col == a.start_col && b.end_col > a.end_col)
+        })
+        .collect();
+    nested_annotations.sort_by_key(|a| cmp::Reverse(a.end_col));
+    let draw_order = secondary_annotations.chain(primary_annotations).chain(nested_annotations);
+    for annotation in draw_order {
+        let columns = underline_columns(annotation);
         if old_school {
-            for p in annotation.start_col..annotation.end_col {
-                if p == annotation.start_col {
+            for (i, &p) in columns.iter().enumerate() {
+                if i == 0 {
                     buffer.putc(line_offset + 1,
                                 width_offset + p,
                                 '^',
@@ -336,26 +1121,31 @@ fn render_source_line(msg: &CompilerMessage,
                 }
             }
         } else {
-            for p in annotation.start_col..annotation.end_col {
+            for &p in &columns {
                 if annotation.is_primary {
                     buffer.putc(line_offset + 1,
                                 width_offset + p,
                                 '^',
                                 Style::UnderlinePrimary);
-                    if !annotation.is_minimized {
-                        buffer.set_style(line_offset,
-                                            width_offset + p,
-                                            Style::UnderlinePrimary);
-                    }
                 } else {
                     buffer.putc(line_offset + 1,
                                 width_offset + p,
                                 '-',
                                 Style::UnderlineSecondary);
-                    if !annotation.is_minimized {
+                }
+            }
+            if !annotation.is_minimized {
+                // The newline doesn't have a source character to highlight
+                let end_col = cmp::min(annotation.end_col, source_cells.len());
+                for &cell in &source_cells[annotation.start_col..end_col] {
+                    if annotation.is_primary {
+                        buffer.set_style(line_offset,
+                                            width_offset + cell,
+                                            Style::Highlight);
+                    } else {
                         buffer.set_style(line_offset,
-                                            width_offset + p,
-                                            Style::UnderlineSecondary);
+                                            width_offset + cell,
+                                            Style::HighlightSecondary);
                     }
                 }
             }
@@ -414,12 +1204,25 @@ fn render_source_line(msg: &CompilerMessage,
     let mut labeled_annotations = &labeled_annotations[..];
     match labeled_annotations.split_last().unwrap() {
         (last, previous) => {
-            if previous.iter()
+            let highlight_label: String = format!(" {}", last.label.as_ref().unwrap());
+
+            // If we're wrapping labels, the label also has to fit on the highlight line
+            let fits = match terminal_width {
+                Some(terminal_width) => {
+                    width_offset + end_column(last) +
+                    highlight_label.chars().count() <= terminal_width
+                }
+                None => true,
+            };
+
+            // The label goes at the end of the highlight line, so it can only be used if
+            // nothing else is underlined further along the line
+            if fits &&
+               previous.iter()
                 .chain(&unlabeled_annotations)
-                .all(|a| !overlaps(a, last)) {
+                .all(|a| !overlaps(a, last) && a.start_col <= last.start_col) {
                 // append the label afterwards; we keep it in a separate
                 // string
-                let highlight_label: String = format!(" {}", last.label.as_ref().unwrap());
                 if last.is_primary {
                     buffer.append(line_offset + 1, &highlight_label, Style::LabelPrimary);
                 } else {
@@ -435,24 +1238,46 @@ fn render_source_line(msg: &CompilerMessage,
         return;
     }
 
+    // Split each label into the rows it will take up. Unless we're wrapping, that's
+    // one row per label.
+    let label_rows: Vec<Vec<String>> = labeled_annotations.iter()
+        .map(|annotation| {
+            let label = annotation.label.as_ref().unwrap();
+            match terminal_width {
+                Some(terminal_width) => {
+                    let start_col = start_column(annotation);
+                    wrap_label(label, terminal_width.saturating_sub(width_offset + start_col))
+                }
+                None => vec![label.clone()],
+            }
+        })
+        .collect();
+
+    // Work out which row each label starts on. Leave one extra line below the
+    // highlight line, then stack the labels so that each one goes below all of the
+    // labels that come after it.
+    let mut first_label_row = vec![0; labeled_annotations.len()];
+    let mut next_row = 3;
+    for index in (0..labeled_annotations.len()).rev() {
+        first_label_row[index] = next_row;
+        next_row += label_rows[index].len();
+    }
+
     for (index, annotation) in labeled_annotations.iter().enumerate() {
-        // Leave:
-        // - 1 extra line
-        // - One line for each thing that comes after
-        let comes_after = labeled_annotations.len() - index - 1;
-        let blank_lines = 3 + comes_after;
+        let start_col = start_column(annotation);
+        let blank_lines = first_label_row[index];
 
         // For each blank line, draw a `|` at our column. The
         // text ought to be long enough for this.
         for index in 2..blank_lines {
             if annotation.is_primary {
                 buffer.putc(line_offset + index,
-                            width_offset + annotation.start_col,
+                            width_offset + start_col,
                             '|',
                             Style::UnderlinePrimary);
             } else {
                 buffer.putc(line_offset + index,
-                            width_offset + annotation.start_col,
+                            width_offset + start_col,
                             '|',
                             Style::UnderlineSecondary);
             }
@@ -462,25 +1287,369 @@ fn render_source_line(msg: &CompilerMessage,
                         Style::LineNumber);
         }
 
-        if annotation.is_primary {
-            buffer.puts(line_offset + blank_lines,
-                        width_offset + annotation.start_col,
-                        annotation.label.as_ref().unwrap(),
-                        Style::LabelPrimary);
+        for (row, label) in label_rows[index].iter().enumerate() {
+            if annotation.is_primary {
+                buffer.puts(line_offset + blank_lines + row,
+                            width_offset + start_col,
+                            label,
+                            Style::LabelPrimary);
+            } else {
+                buffer.puts(line_offset + blank_lines + row,
+                            width_offset + start_col,
+                            label,
+                            Style::LabelSecondary);
+            }
+            buffer.puts(line_offset + blank_lines + row,
+                        width_offset - 2,
+                        "|>",
+                        Style::LineNumber);
+        }
+    }
+}
+
+/// Cut `source` down to the columns in `range`, with `...` on either side where
+/// something was cut off. Also returns the mapping from a column of `source` to its column
+/// in the cut down line; columns out of view end up on the nearest `...`.
+fn visible_column_range(source: &str, range: &Range<usize>) -> (String, impl Fn(usize) -> usize) {
+    let chars: Vec<char> = source.chars().collect();
+    let start = cmp::min(range.start, chars.len());
+    let end = cmp::max(start, cmp::min(range.end, chars.len()));
+
+    let mut visible = String::new();
+    let prefix_len = if start > 0 { 3 } else { 0 };
+    if start > 0 {
+        visible.push_str("...");
+    }
+    visible.extend(&chars[start..end]);
+    let is_cut_at_end = end < chars.len();
+    if is_cut_at_end {
+        visible.push_str("...");
+    }
+
+    let visible_len = visible.chars().count();
+    let map_column = move |col: usize| -> usize {
+        if col < start {
+            0
+        } else if col > end && is_cut_at_end {
+            visible_len - 3
         } else {
-            buffer.puts(line_offset + blank_lines,
-                        width_offset + annotation.start_col,
-                        annotation.label.as_ref().unwrap(),
-                        Style::LabelSecondary);
+            col - start + prefix_len
         }
-        buffer.puts(line_offset + blank_lines,
-                    width_offset - 2,
-                    "|>",
-                    Style::LineNumber);
+    };
+    (visible, map_column)
+}
+
+/// Split a label into lines of at most `width` characters, breaking at spaces. Words
+/// longer than `width` are left whole on a line of their own.
+fn wrap_label(label: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut current = String::new();
+
+    for word in label.split(' ') {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width {
+            lines.push(current);
+            current = String::new();
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    lines.push(current);
+
+    lines
+}
+
+/// Convert a character index within `s` into the column it is displayed at. Wide
+/// characters (eg, emoji and CJK) take up two columns, and positions past the end of
+/// the line are treated as one column each.
+pub fn display_column(s: &str, char_idx: usize) -> usize {
+    let mut col = 0;
+    let mut chars_seen = 0;
+    for c in s.chars().take(char_idx) {
+        col += char_width(c, col);
+        chars_seen += 1;
+    }
+    col + (char_idx - chars_seen)
+}
+
+/// For lines containing right-to-left text, the column that each character of `s` is
+/// displayed at once the terminal has laid the line out with the Unicode Bidi algorithm.
+/// Returns None if the line is entirely left-to-right.
+fn visual_columns(s: &str) -> Option<Vec<usize>> {
+    let bidi_info = BidiInfo::new(s, None);
+    if !bidi_info.has_rtl() {
+        return None;
+    }
+
+    let para = &bidi_info.paragraphs[0];
+    let levels = bidi_info.reordered_levels_per_char(para, para.range.clone());
+    let chars: Vec<char> = s.chars().collect();
+
+    let mut columns = vec![0; chars.len()];
+    let mut col = 0;
+    for logical_idx in BidiInfo::reorder_visual(&levels) {
+        columns[logical_idx] = col;
+        col += chars[logical_idx].width().unwrap_or(1);
+    }
+    Some(columns)
+}
+
+/// Render one annotated line of the primary span's file by its 1-based line number: the
+/// source line and the underline and label rows below it. Returns None if no label
+/// starts on that line.
+pub fn source_line_at(msg: &CompilerMessage,
+                      line_number: usize)
+                      -> Option<Vec<Vec<StyledString>>> {
+    msg.mark_emitted();
+    let primary_lo = msg.cm.lookup_char_pos(msg.primary_span.lo);
+    let annotated_files = preprocess_annotations(msg);
+    let primary_file = annotated_files.iter().find(|x| x.file.name == primary_lo.file.name);
+    let annotated_file = match primary_file {
+        Some(annotated_file) => annotated_file,
+        None => return None,
+    };
+    let line = match annotated_file.lines.iter().find(|x| x.line_number == line_number) {
+        Some(line) => line,
+        None => return None,
+    };
+
+    // Keep the same gutter as when the whole message is rendered
+    let len_of_max_line_num = get_max_line_num(msg).to_string().len();
+    let mut buffer = StyledBuffer::new();
+    render_source_line(msg,
+                       &mut buffer,
+                       annotated_file.file.clone(),
+                       line,
+                       3 + len_of_max_line_num,
+                       None,
+                       check_old_school());
+    Some(buffer.render())
+}
+
+/// The display width of each character of the source line containing `span.lo`, with tabs
+/// as wide as they are when rendered
+pub fn char_width_map(msg: &CompilerMessage, span: Span) -> Vec<usize> {
+    let lo = msg.cm.lookup_char_pos(span.lo);
+    if lo.line == 0 {
+        return vec![];
+    }
+
+    let mut col = 0;
+    get_source_line(msg, &lo.file, lo.line - 1)
+        .chars()
+        .map(|c| {
+            let width = char_width(c, col);
+            col += width;
+            width
+        })
+        .collect()
+}
+
+/// Tabs are expanded to the next multiple of this many columns
+const TAB_WIDTH: usize = 4;
+
+/// The number of columns `c` takes up when it's displayed starting at column `col`
+fn char_width(c: char, col: usize) -> usize {
+    if c == '\t' {
+        TAB_WIDTH - (col % TAB_WIDTH)
+    } else {
+        // Other control characters don't have a width, so count them as one column
+        c.width().unwrap_or(1)
     }
 }
 
+/// Replace the tabs in `s` with spaces up to the next tab stop. Also returns, for each
+/// character of `s`, the position of that character in the expanded string.
+pub fn expand_tabs(s: &str) -> (String, Vec<usize>) {
+    let mut expanded = String::new();
+    let mut positions = vec![];
+    let mut position = 0;
+    let mut col = 0;
+
+    for c in s.chars() {
+        positions.push(position);
+        let width = char_width(c, col);
+        if c == '\t' {
+            for _ in 0..width {
+                expanded.push(' ');
+            }
+            position += width;
+        } else {
+            expanded.push(c);
+            position += 1;
+        }
+        col += width;
+    }
+
+    (expanded, positions)
+}
+
 fn overlaps(a1: &Annotation, a2: &Annotation) -> bool {
-    (a2.start_col..a2.end_col).contains(a1.start_col) ||
-    (a1.start_col..a1.end_col).contains(a2.start_col)
+    a2.covers(a1.start_col) || a1.covers(a2.start_col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codemap;
+
+    fn ann(start_col: usize, end_col: usize) -> Annotation {
+        Annotation::new(start_col, end_col, false, false)
+    }
+
+    #[test]
+    fn overlaps_disjoint() {
+        assert!(!overlaps(&ann(0, 2), &ann(5, 7)));
+        assert!(!overlaps(&ann(5, 7), &ann(0, 2)));
+    }
+
+    #[test]
+    fn overlaps_adjacent() {
+        // end_col is exclusive, so touching ranges don't overlap
+        assert!(!overlaps(&ann(0, 3), &ann(3, 5)));
+        assert!(!overlaps(&ann(3, 5), &ann(0, 3)));
+    }
+
+    #[test]
+    fn overlaps_partial_left() {
+        assert!(overlaps(&ann(0, 4), &ann(2, 6)));
+    }
+
+    #[test]
+    fn overlaps_partial_right() {
+        assert!(overlaps(&ann(2, 6), &ann(0, 4)));
+    }
+
+    #[test]
+    fn overlaps_contained() {
+        assert!(overlaps(&ann(0, 10), &ann(3, 5)));
+        assert!(overlaps(&ann(3, 5), &ann(0, 10)));
+    }
+
+    #[test]
+    fn overlaps_identical() {
+        assert!(overlaps(&ann(2, 5), &ann(2, 5)));
+    }
+
+    #[test]
+    fn overlaps_zero_width() {
+        // A zero-width annotation overlaps anything it starts inside of...
+        assert!(overlaps(&ann(3, 3), &ann(0, 10)));
+        assert!(overlaps(&ann(0, 10), &ann(3, 3)));
+        // ...but not another zero-width annotation, even at the same column
+        assert!(!overlaps(&ann(3, 3), &ann(3, 3)));
+        assert!(!overlaps(&ann(3, 3), &ann(5, 7)));
+    }
+
+    #[test]
+    fn overlaps_one_character() {
+        assert!(overlaps(&ann(0, 3), &ann(2, 5)));
+        assert!(overlaps(&ann(2, 5), &ann(0, 3)));
+    }
+
+    #[test]
+    fn covers_is_end_exclusive() {
+        let a = ann(2, 5);
+        assert!(!a.covers(1));
+        assert!(a.covers(2));
+        assert!(a.covers(4));
+        assert!(!a.covers(5));
+        assert!(!ann(3, 3).covers(3));
+    }
+
+    #[test]
+    fn split_at_inside() {
+        let a = ann(2, 8).with_label(String::from("label"));
+
+        let (first, second) = a.split_at(5).unwrap();
+        assert_eq!((first.start_col, first.end_col), (2, 5));
+        assert_eq!((second.start_col, second.end_col), (5, 8));
+        assert_eq!(first.label, Some(String::from("label")));
+        assert_eq!(second.label, None);
+    }
+
+    #[test]
+    fn split_at_outside() {
+        assert!(ann(2, 8).split_at(1).is_none());
+        assert!(ann(2, 8).split_at(2).is_none());
+        assert!(ann(2, 8).split_at(8).is_none());
+        assert!(ann(2, 8).split_at(9).is_none());
+    }
+
+    #[test]
+    fn merge_overlapping_and_adjacent() {
+        let a = ann(2, 5).with_label(String::from("first"));
+        let b = ann(5, 8).with_label(String::from("second"));
+
+        let merged = Annotation::merge(&b, &a).unwrap();
+        assert_eq!((merged.start_col, merged.end_col), (2, 8));
+        assert_eq!(merged.label, Some(String::from("first, second")));
+
+        let merged = Annotation::merge(&ann(0, 4), &ann(2, 3)).unwrap();
+        assert_eq!((merged.start_col, merged.end_col), (0, 4));
+        assert_eq!(merged.label, None);
+    }
+
+    #[test]
+    fn merge_incompatible() {
+        assert!(Annotation::merge(&ann(0, 2), &ann(3, 5)).is_none());
+
+        let primary = Annotation::new(2, 4, true, false);
+        assert!(Annotation::merge(&ann(0, 3), &primary).is_none());
+    }
+
+    #[test]
+    fn as_annotation() {
+        let cm = CodeMap::new();
+        let label = SpanLabel::primary(codemap::mk_sp(BytePos(4), BytePos(6)),
+                                       String::from("label"));
+        assert!(label.as_annotation(&cm).is_none());
+
+        cm.new_filemap_and_lines("foo.rs", "fn foo() {}\n");
+        let annotation = label.as_annotation(&cm).unwrap();
+        assert_eq!((annotation.start_col, annotation.end_col), (4, 6));
+        assert!(annotation.is_primary);
+        assert_eq!(annotation.label, Some(String::from("label")));
+
+        let empty = SpanLabel::secondary(codemap::mk_sp(BytePos(3), BytePos(3)), None);
+        let annotation = empty.as_annotation(&cm).unwrap();
+        assert_eq!((annotation.start_col, annotation.end_col), (3, 4));
+
+        let outside = SpanLabel::secondary(codemap::mk_sp(BytePos(40), BytePos(42)), None);
+        assert!(outside.as_annotation(&cm).is_none());
+    }
+
+    #[test]
+    fn old_school_env_values() {
+        assert!(old_school_env_enables(Some("1")));
+        assert!(old_school_env_enables(Some("true")));
+        assert!(old_school_env_enables(Some("TRUE")));
+        assert!(!old_school_env_enables(Some("0")));
+        assert!(!old_school_env_enables(Some("false")));
+        assert!(!old_school_env_enables(Some("")));
+        assert!(!old_school_env_enables(None));
+    }
+
+    #[test]
+    fn cached_lookup_matches_codemap() {
+        let cm = CodeMap::new();
+        let mut positions = vec![];
+        let files = [("foo.rs", "fn foo() {\n    résumé();\n}\n"), ("bar.rs", "x\n\ny")];
+        for &(name, src) in &files {
+            let file = cm.new_filemap_and_lines(name, src);
+            positions.extend(src.char_indices().map(|(i, _)| file.start_pos + BytePos(i as u32)));
+            positions.push(file.end_pos);
+        }
+
+        // Every position, in order and then backwards, so that the cache is both hit and
+        // missed at line and file boundaries
+        let mut cache = None;
+        for &pos in positions.iter().chain(positions.iter().rev()) {
+            let cached = cached_lookup(&cm, &mut cache, pos);
+            let expected = cm.lookup_char_pos(pos);
+            assert_eq!((&cached.file.name, cached.line, cached.col),
+                       (&expected.file.name, expected.line, expected.col));
+        }
+    }
 }
@@ -0,0 +1,48 @@
+use styled_buffer::Level;
+use compiler_message::*;
+
+/// Render the message in the format that VS Code's `$rustc` problem matcher parses, so that
+/// tasks defined in `tasks.json` can pick the message up without a matcher of their own.
+///
+/// eg)
+///
+/// ```text
+/// error[E0425]: Unresolved name
+///   --> foo.rs:3:14
+/// ```
+///
+/// Note that the matcher counts columns from 1.
+pub fn render_as_vscode_problem_matcher(msg: &CompilerMessage) -> String {
+    let mut output = String::new();
+
+    let severity = match msg.level {
+        Level::Bug | Level::Fatal | Level::PhaseFatal | Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Note => "note",
+        Level::Help => "help",
+        Level::Cancelled => return output,
+    };
+    msg.mark_emitted();
+
+    output.push_str(severity);
+    if let Some(ref code) = msg.error_code {
+        output.push_str(&format!("[{}]", code));
+    }
+    output.push_str(&format!(": {}\n", msg.primary_msg));
+
+    // The override counts columns from 0, like the `-->` line
+    let location = match msg.location_override {
+        Some((ref file, line, col)) => Some((file.clone(), line, col + 1)),
+        None => {
+            msg.file_and_line_for_span(msg.primary_span).map(|(file, line)| {
+                let loc = msg.cm.lookup_char_pos(msg.primary_span.lo);
+                (file, line, loc.col.0 + 1)
+            })
+        }
+    };
+    if let Some((file, line, col)) = location {
+        output.push_str(&format!("  --> {}:{}:{}\n", file, line, col));
+    }
+
+    output
+}
@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+
+use styled_buffer::*;
+use compiler_message::*;
+use render_succinct;
+use styles;
+
+/// A strategy for turning a message into output. The three parts are rendered in
+/// order: the header, the annotated source, then the footer.
+pub trait Renderer {
+    fn render_header(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()>;
+    fn render_source_block(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()>;
+    fn render_footer(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()>;
+}
+
+/// The human-readable output of `render_succinct`, as plain text
+pub struct HumanRenderer;
+
+/// The single-line JSON object of `render_compact_json`
+pub struct JsonRenderer;
+
+impl Renderer for HumanRenderer {
+    fn render_header(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()> {
+        let mut buffer = StyledBuffer::new();
+        render_succinct::render_header(msg, &mut buffer);
+        try!(write_plain(render_succinct::padding_rows(msg.padding), buf));
+        write_plain(buffer.render(), buf)
+    }
+
+    fn render_source_block(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()> {
+        let mut buffer = StyledBuffer::new();
+        render_succinct::render_source_lines(msg, &mut buffer);
+        write_plain(buffer.render(), buf)
+    }
+
+    fn render_footer(&self, msg: &CompilerMessage, buf: &mut Write) -> io::Result<()> {
+        let mut buffer = StyledBuffer::new();
+        render_succinct::render_footer(msg, &mut buffer);
+        try!(write_plain(buffer.render(), buf));
+        try!(write_plain(render_succinct::render_related(msg, None), buf));
+        write_plain(render_succinct::padding_rows(msg.padding), buf)
+    }
+}
+
+/// A text buffer that rendered output can be appended to piece by piece, along with the
+/// style of each piece, eg) the rope of an editor buffer. See
+/// `CompilerMessage::render_to_rope`.
+pub trait RopeWriter {
+    fn append(&mut self, text: &str, style: Style);
+}
+
+/// Append each styled piece of `lines` to `rope`, with a newline (in `Style::NoStyle`) at
+/// the end of each line
+pub fn write_rope(lines: Vec<Vec<StyledString>>, rope: &mut RopeWriter) {
+    for line in lines {
+        for part in line {
+            rope.append(&part.text, part.style);
+        }
+        rope.append("\n", Style::NoStyle);
+    }
+}
+
+/// Write each line followed by a newline, dropping the styles
+pub fn write_plain(lines: Vec<Vec<StyledString>>, buf: &mut Write) -> io::Result<()> {
+    for line in lines {
+        for part in line {
+            try!(buf.write_all(part.text.as_bytes()));
+        }
+        try!(buf.write_all(b"\n"));
+    }
+    Ok(())
+}
+
+/// Like `write_plain`, but with the ANSI escape codes for each style (see `styles`), for
+/// terminals that understand them
+pub fn write_ansi(lines: Vec<Vec<StyledString>>, level: Level, buf: &mut Write) -> io::Result<()> {
+    for line in lines {
+        for part in line {
+            let code = styles::style_ansi(part.style, level);
+            if code.is_empty() {
+                try!(buf.write_all(part.text.as_bytes()));
+            } else {
+                try!(write!(buf, "{}{}{}", code, part.text, styles::RESET_ANSI));
+            }
+        }
+        try!(buf.write_all(b"\n"));
+    }
+    Ok(())
+}
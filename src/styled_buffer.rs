@@ -24,11 +24,15 @@ pub enum Style {
     Quotation,
     UnderlinePrimary,
     UnderlineSecondary,
+    Highlight,
+    HighlightSecondary,
     LabelPrimary,
     LabelSecondary,
     OldSchoolNoteText,
     OldSchoolNote,
     NoStyle,
+    /// The blank rows added around a message by `CompilerMessage::with_padding`
+    Padding,
     ErrorCode,
     Level(Level),
 }
@@ -43,6 +47,8 @@ pub struct StyledString {
 pub struct StyledBuffer {
     text: Vec<Vec<char>>,
     styles: Vec<Vec<Style>>,
+    /// How much room to make in each new row up front
+    col_capacity: usize,
 }
 
 impl Level {
@@ -50,12 +56,32 @@ impl Level {
         match self {
             Level::Bug | Level::Fatal | Level::PhaseFatal | Level::Error => term::color::BRIGHT_RED,
             Level::Warning => term::color::YELLOW,
-            Level::Note => term::color::BRIGHT_GREEN,
+            Level::Note => term::color::BRIGHT_BLUE,
             Level::Help => term::color::BRIGHT_CYAN,
             Level::Cancelled => unreachable!(),
         }
     }
 
+    /// The standard ANSI color code (as used in `\x1b[<code>m`) for this level. Cancelled
+    /// messages get the terminal's default color.
+    pub fn ansi_color(self) -> u8 {
+        match self {
+            Level::Bug | Level::Fatal | Level::PhaseFatal | Level::Error => 31,
+            Level::Warning => 33,
+            Level::Note => 34,
+            Level::Help => 36,
+            Level::Cancelled => 39,
+        }
+    }
+
+    /// Whether the level label should be shown in bold
+    pub fn bold(self) -> bool {
+        match self {
+            Level::Cancelled => false,
+            _ => true,
+        }
+    }
+
     pub fn to_string(self) -> String {
         let output = match self {
             Level::Bug => "error: internal compiler error",
@@ -69,11 +95,24 @@ impl Level {
     }
 }
 
+impl fmt::Display for StyledString {
+    /// Just the text, without any styling
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
 impl StyledBuffer {
     pub fn new() -> StyledBuffer {
+        StyledBuffer::with_capacity(0, 0)
+    }
+
+    /// Create a buffer with room for `rows` rows of `cols` columns before it has to grow
+    pub fn with_capacity(rows: usize, cols: usize) -> StyledBuffer {
         StyledBuffer {
-            text: vec![],
-            styles: vec![],
+            text: Vec::with_capacity(rows),
+            styles: Vec::with_capacity(rows),
+            col_capacity: cols,
         }
     }
 
@@ -116,8 +155,8 @@ impl StyledBuffer {
 
     fn ensure_lines(&mut self, line: usize) {
         while line >= self.text.len() {
-            self.text.push(vec![]);
-            self.styles.push(vec![]);
+            self.text.push(Vec::with_capacity(self.col_capacity));
+            self.styles.push(Vec::with_capacity(self.col_capacity));
         }
     }
 
@@ -6,6 +6,7 @@ use term;
 use styled_buffer::*;
 
 use compiler_message::*;
+use render_succinct::render_succinct;
 
 pub enum Destination {
     Terminal(Box<term::StderrTerminal>),
@@ -34,21 +35,22 @@ impl Destination {
             Style::Quotation => {}
             Style::OldSchoolNote => {
                 try!(self.start_attr(term::Attr::Bold));
-                try!(self.start_attr(term::Attr::ForegroundColor(term::color::BRIGHT_GREEN)));
+                try!(self.start_attr(term::Attr::ForegroundColor(term::color::BRIGHT_BLUE)));
             }
             Style::OldSchoolNoteText | Style::HeaderMsg => {
                 try!(self.start_attr(term::Attr::Bold));
             }
-            Style::UnderlinePrimary | Style::LabelPrimary => {
+            Style::UnderlinePrimary | Style::LabelPrimary | Style::Highlight => {
                 try!(self.start_attr(term::Attr::Bold));
                 try!(self.start_attr(term::Attr::ForegroundColor(lvl.color())));
             }
             Style::UnderlineSecondary |
-            Style::LabelSecondary => {
+            Style::LabelSecondary |
+            Style::HighlightSecondary => {
                 try!(self.start_attr(term::Attr::Bold));
                 try!(self.start_attr(term::Attr::ForegroundColor(term::color::BRIGHT_BLUE)));
             }
-            Style::NoStyle => {}
+            Style::NoStyle | Style::Padding => {}
             Style::Level(Level::Error) => {
                 try!(self.start_attr(term::Attr::Bold));
                 try!(self.start_attr(term::Attr::ForegroundColor(term::color::BRIGHT_RED)));
@@ -143,14 +145,85 @@ impl Write for Destination {
 
 pub fn emit(level: Level, msg: Vec<Vec<StyledString>>) -> io::Result<()> {
     let mut dst = Destination::from_stderr();
+    emit_to(&mut dst, level, msg)
+}
+
+/// Emit each message to stderr in turn, with a blank line between them. Unlike calling
+/// `emit` for each one, the terminal is only looked up once.
+pub fn render_all_to_stderr(msgs: &[CompilerMessage]) -> io::Result<()> {
+    let mut dst = Destination::from_stderr();
+    render_all_to(&mut dst, msgs)
+}
+
+fn render_all_to(dst: &mut Destination, msgs: &[CompilerMessage]) -> io::Result<()> {
+    let msgs = msgs.iter().filter(|msg| msg.level != Level::Cancelled && !msg.suppressed);
+    for (i, msg) in msgs.enumerate() {
+        if i > 0 {
+            try!(write!(dst, "\n"));
+        }
+        try!(emit_to(dst, msg.level, render_succinct(msg)));
+    }
+    Ok(())
+}
 
+fn emit_to(dst: &mut Destination, level: Level, msg: Vec<Vec<StyledString>>) -> io::Result<()> {
     for line in msg {
         for part in line {
             dst.apply_style(level, part.style);
-            write!(&mut dst, "{}", part.text);
+            write!(dst, "{}", part.text);
             dst.reset_attrs()?;
         }
-        write!(&mut dst, "\n");
+        write!(dst, "\n");
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use compiler_message::CompilerMessage;
+    use super::*;
+
+    /// A writer that can still be read from after being boxed up in a `Destination`
+    #[derive(Clone)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn render_all_separates_messages_in_order() {
+        let mut first = CompilerMessage::with_source_and_span_bytes("let x = 1;\n", 4..5);
+        first.primary_msg = String::from("first");
+        let mut second = CompilerMessage::with_source_and_span_bytes("let y = 2;\n", 8..9);
+        second.primary_msg = String::from("second");
+
+        let buf = SharedBuf(Arc::new(Mutex::new(vec![])));
+        let mut dst = Destination::Raw(Box::new(buf.clone()));
+        render_all_to(&mut dst, &[first, second]).unwrap();
+
+        let text = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(&text[..],
+                   &r#"
+error: first
+ --> <anon>:1:4
+  |>
+1 |>let x = 1;
+  |>    ^
+
+error: second
+ --> <anon>:1:8
+  |>
+1 |>let y = 2;
+  |>        ^
+"#[1..]);
+    }
+}
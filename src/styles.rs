@@ -0,0 +1,90 @@
+//! The ANSI escape codes for each `Style`, for building custom output on top of the
+//! `StyledString`s the renderers produce. These match the colors used when emitting to a
+//! terminal.
+
+use styled_buffer::{Level, Style};
+
+/// Clears any styles set by the codes below
+pub const RESET_ANSI: &'static str = "\x1b[0m";
+
+/// Bold red, for errors (and bugs and fatal errors)
+pub const ERROR_ANSI: &'static str = "\x1b[31;1m";
+
+/// Bold yellow, for warnings
+pub const WARNING_ANSI: &'static str = "\x1b[33;1m";
+
+/// Bold blue, for notes
+pub const NOTE_ANSI: &'static str = "\x1b[34;1m";
+
+/// Bold cyan, for help
+pub const HELP_ANSI: &'static str = "\x1b[36;1m";
+
+/// Bold blue, for line numbers, the `|>` gutter and secondary underlines and labels
+pub const LINE_NUMBER_ANSI: &'static str = "\x1b[34;1m";
+
+/// Bold magenta, for error codes
+pub const ERROR_CODE_ANSI: &'static str = "\x1b[35;1m";
+
+/// Bold, for the header message
+pub const BOLD_ANSI: &'static str = "\x1b[1m";
+
+/// No styling at all
+pub const NO_STYLE_ANSI: &'static str = "";
+
+/// The color of a message level, from `Level::ansi_color` and `Level::bold`
+pub fn level_ansi(lvl: Level) -> String {
+    if lvl.bold() {
+        format!("\x1b[{};1m", lvl.ansi_color())
+    } else {
+        format!("\x1b[{}m", lvl.ansi_color())
+    }
+}
+
+/// The escape code to start `style`, in a message of level `lvl`. Primary underlines and
+/// labels take the color of the message's level.
+pub fn style_ansi(style: Style, lvl: Level) -> String {
+    let code = match style {
+        Style::FileNameStyle |
+        Style::LineAndColumn |
+        Style::Quotation |
+        Style::NoStyle |
+        Style::Padding => NO_STYLE_ANSI,
+        Style::LineNumber => LINE_NUMBER_ANSI,
+        Style::ErrorCode => ERROR_CODE_ANSI,
+        Style::OldSchoolNote => NOTE_ANSI,
+        Style::OldSchoolNoteText | Style::HeaderMsg => BOLD_ANSI,
+        Style::UnderlinePrimary | Style::LabelPrimary | Style::Highlight => return level_ansi(lvl),
+        Style::UnderlineSecondary | Style::LabelSecondary | Style::HighlightSecondary => {
+            LINE_NUMBER_ANSI
+        }
+        Style::Level(Level::Error) => ERROR_ANSI,
+        Style::Level(Level::Warning) => WARNING_ANSI,
+        Style::Level(Level::Note) => BOLD_ANSI,
+        Style::Level(_) => NO_STYLE_ANSI,
+    };
+    String::from(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primary_styles_follow_the_level() {
+        assert_eq!(style_ansi(Style::UnderlinePrimary, Level::Error), ERROR_ANSI);
+        assert_eq!(style_ansi(Style::LabelPrimary, Level::Warning), WARNING_ANSI);
+        assert_eq!(style_ansi(Style::UnderlineSecondary, Level::Error), LINE_NUMBER_ANSI);
+        assert_eq!(style_ansi(Style::NoStyle, Level::Error), NO_STYLE_ANSI);
+    }
+
+    #[test]
+    fn level_colors_match_the_constants() {
+        assert_eq!(level_ansi(Level::Bug), ERROR_ANSI);
+        assert_eq!(level_ansi(Level::Error), ERROR_ANSI);
+        assert_eq!(level_ansi(Level::Warning), WARNING_ANSI);
+        assert_eq!(level_ansi(Level::Note), NOTE_ANSI);
+        assert_eq!(Level::Note.ansi_color(), 34);
+        assert_eq!(level_ansi(Level::Help), HELP_ANSI);
+        assert_eq!(level_ansi(Level::Cancelled), "\x1b[39m");
+    }
+}
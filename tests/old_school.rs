@@ -0,0 +1,72 @@
+//! `set_old_school` and `ERROR_REPORTER_OLD_SCHOOL` switch the format for the whole
+//! process, so this test gets a binary of its own rather than racing the tests in
+//! `src/lib.rs`.
+
+extern crate error_reporter;
+
+use std::rc::Rc;
+
+use error_reporter::*;
+
+fn make_string(lines: Vec<Vec<StyledString>>) -> String {
+    lines.iter()
+        .flat_map(|rl| {
+            rl.iter()
+                .map(|s| &s.text[..])
+                .chain(Some("\n"))
+        })
+        .collect()
+}
+
+#[test]
+fn test_set_old_school() {
+    let file_text = r#"
+fn foo() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let cm = Rc::new(CodeMap::new());
+    let foo = cm.new_filemap_and_lines("foo.rs", file_text);
+    let lo = file_text.rfind("vec").unwrap() as u32;
+    let span_vec1 = Span {
+        lo: foo.start_pos + BytePos(lo),
+        hi: foo.start_pos + BytePos(lo + 3),
+        expn_id: NO_EXPANSION,
+    };
+
+    let mut err = CompilerMessage::new(Level::Error,
+                                       String::from("Unresolved name"),
+                                       span_vec1,
+                                       None,
+                                       cm);
+    err.span_label(span_vec1, Some(String::from("primary message")));
+
+    let old_school = &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^~~
+"#[1..];
+    let usual = &r#"
+error: Unresolved name
+ --> foo.rs:3:13
+  |>
+3 |>    vec.push(vec.pop().unwrap());
+  |>             ^^^ primary message
+"#[1..];
+
+    // Until set_old_school is called, the environment decides
+    std::env::set_var("ERROR_REPORTER_OLD_SCHOOL", "1");
+    assert_eq!(&make_string(render_succinct(&err))[..], old_school);
+
+    // After that, the setter wins, even over the environment
+    set_old_school(false);
+    assert_eq!(&make_string(render_succinct(&err))[..], usual);
+
+    set_old_school(true);
+    assert_eq!(&make_string(render_succinct(&err))[..], old_school);
+
+    set_old_school(false);
+    assert_eq!(&make_string(render_succinct(&err))[..], usual);
+}